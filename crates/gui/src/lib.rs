@@ -19,8 +19,10 @@ pub mod prelude {
 
 pub trait Application {
 	type Error: std::fmt::Debug;
+	/// Whatever the application is started with, handed to `setup`
+	type Config;
 
-	fn setup(system: &mut System) -> Self;
+	fn setup(system: &mut System, config: Self::Config) -> Self;
 	fn handle_event(
 		&mut self,
 		event: prelude::Event,
@@ -44,10 +46,13 @@ impl Options {
 	}
 }
 
-pub fn run<App: 'static + Application>(options: Options) -> Result<(), App::Error> {
+pub fn run<App: 'static + Application>(
+	options: Options,
+	config: App::Config,
+) -> Result<(), App::Error> {
 	let mut system = init(&options);
 
-	let mut app = App::setup(&mut system);
+	let mut app = App::setup(&mut system, config);
 
 	let System {
 		event_loop,
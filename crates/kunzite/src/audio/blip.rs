@@ -0,0 +1,160 @@
+use std::f64::consts::PI;
+
+/// Sub-sample positions a step can start at
+const PHASES: usize = 32;
+/// Width of a step in output samples
+const TAPS: usize = 16;
+/// Fractional bits of the fixed point sample positions
+const FRAC_BITS: u32 = 32;
+
+/// Band-limited synthesis buffer, in the spirit of blargg's Blip_Buffer
+///
+/// Instead of sampling the channels, changes in amplitude are added as
+/// band-limited steps at the clock they happen. The steps are stored as
+/// their derivative and integrated again when samples are read, which
+/// leaves nothing above the output Nyquist frequency to alias.
+pub struct BlipBuf {
+	/// Output samples per clock, fixed point
+	factor: u64,
+	/// Output samples covered by the clocks ended so far, fixed point
+	offset: u64,
+	/// Windowed sinc impulse for each phase
+	kernel: Vec<[f32; TAPS]>,
+	/// Pending deltas, sample 0 is the next one to be read
+	deltas: Vec<f32>,
+	/// Running sum of the deltas read so far
+	integrator: f32,
+}
+
+impl BlipBuf {
+	pub fn new(clock_rate: u32, sample_rate: f64) -> Self {
+		let mut blip = Self {
+			factor: 0,
+			offset: 0,
+			kernel: kernel(),
+			deltas: vec![0.0; TAPS],
+			integrator: 0.0,
+		};
+		blip.set_rates(clock_rate, sample_rate);
+
+		blip
+	}
+
+	/// Changes the ratio, the sample rate may be fractional for rate control
+	pub fn set_rates(&mut self, clock_rate: u32, sample_rate: f64) {
+		self.factor = (sample_rate / clock_rate as f64 * (1u64 << FRAC_BITS) as f64) as u64;
+	}
+
+	/// Adds a change in amplitude `clock` clocks after the end of the last
+	/// frame
+	pub fn add_delta(&mut self, clock: u32, delta: f32) {
+		let pos = self.offset + clock as u64 * self.factor;
+		let index = (pos >> FRAC_BITS) as usize;
+		let phase = ((pos >> (FRAC_BITS - 5)) & (PHASES as u64 - 1)) as usize;
+
+		if self.deltas.len() < index + TAPS {
+			self.deltas.resize(index + TAPS, 0.0);
+		}
+
+		let kernel = &self.kernel[phase];
+		for (out, k) in self.deltas[index..index + TAPS].iter_mut().zip(kernel) {
+			*out += delta * k;
+		}
+	}
+
+	/// Ends a frame of `clocks` clocks, making its samples available
+	pub fn end_frame(&mut self, clocks: u32) {
+		self.offset += clocks as u64 * self.factor;
+	}
+
+	/// Samples that are complete and can be read
+	pub fn available(&self) -> usize {
+		(self.offset >> FRAC_BITS) as usize
+	}
+
+	/// Appends every available sample to `out`
+	pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+		let count = self.available();
+		if self.deltas.len() < count + TAPS {
+			self.deltas.resize(count + TAPS, 0.0);
+		}
+
+		for delta in &self.deltas[..count] {
+			self.integrator += delta;
+			out.push(self.integrator);
+		}
+
+		self.deltas.drain(..count);
+		self.offset -= (count as u64) << FRAC_BITS;
+	}
+}
+
+/// Builds the impulse of a band-limited step for every phase
+///
+/// A Blackman windowed sinc cut off a little below Nyquist, each phase is
+/// normalised so a step always ends up at its full height.
+fn kernel() -> Vec<[f32; TAPS]> {
+	const CUTOFF: f64 = 0.9;
+
+	(0..PHASES)
+		.map(|phase| {
+			let mut taps = [0.0; TAPS];
+			let shift = phase as f64 / PHASES as f64;
+
+			for (i, tap) in taps.iter_mut().enumerate() {
+				let x = i as f64 - (TAPS / 2) as f64 + 1.0 - shift;
+				let sinc = if x == 0.0 {
+					1.0
+				} else {
+					(PI * CUTOFF * x).sin() / (PI * CUTOFF * x)
+				};
+				let w = (x + TAPS as f64 / 2.0) / TAPS as f64;
+				let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+				*tap = sinc * window;
+			}
+
+			let sum: f64 = taps.iter().sum();
+			let mut kernel = [0.0; TAPS];
+			for (k, tap) in kernel.iter_mut().zip(&taps) {
+				*k = (tap / sum) as f32;
+			}
+
+			kernel
+		})
+		.collect()
+}
+
+/// Output high-pass filter, the capacitor in series with the amplifier
+///
+/// Removes the DC offset the DACs produce, so silence settles back at 0.
+pub struct HighPass {
+	capacitor: f32,
+	/// Charge kept per output sample
+	factor: f32,
+}
+
+impl HighPass {
+	/// Charge kept per clock on DMG and CGB, from the hardware measurements
+	const DMG_CHARGE: f64 = 0.999958;
+	const CGB_CHARGE: f64 = 0.998943;
+
+	pub fn new(cgb: bool, clock_rate: u32, sample_rate: f64) -> Self {
+		let charge = if cgb {
+			Self::CGB_CHARGE
+		} else {
+			Self::DMG_CHARGE
+		};
+
+		Self {
+			capacitor: 0.0,
+			factor: charge.powf(clock_rate as f64 / sample_rate) as f32,
+		}
+	}
+
+	pub fn apply(&mut self, input: f32) -> f32 {
+		let output = input - self.capacitor;
+		self.capacitor = input - output * self.factor;
+
+		output
+	}
+}
@@ -0,0 +1,55 @@
+/// Volume envelope of the square and noise channels
+///
+/// Clocked at 64 Hz by the frame sequencer.
+#[derive(Default)]
+pub struct Envelope {
+	/// Initial volume, direction and period, NRx2
+	reg: u8,
+	/// Current volume, 0-15
+	volume: u8,
+	timer: u8,
+}
+
+impl Envelope {
+	pub fn write(&mut self, val: u8) {
+		self.reg = val;
+	}
+
+	/// The DAC is off when the upper 5 bits of NRx2 are all clear
+	pub fn dac_enabled(&self) -> bool {
+		self.reg & 0xF8 > 0
+	}
+
+	pub fn volume(&self) -> u8 {
+		self.volume
+	}
+
+	fn period(&self) -> u8 {
+		self.reg & 0x7
+	}
+
+	pub fn trigger(&mut self) {
+		self.volume = self.reg >> 4;
+		self.timer = self.period();
+	}
+
+	pub fn clock(&mut self) {
+		if self.period() == 0 {
+			return;
+		}
+
+		self.timer = self.timer.saturating_sub(1);
+		if self.timer > 0 {
+			return;
+		}
+		self.timer = self.period();
+
+		if self.reg & 0x08 > 0 {
+			if self.volume < 15 {
+				self.volume += 1;
+			}
+		} else if self.volume > 0 {
+			self.volume -= 1;
+		}
+	}
+}
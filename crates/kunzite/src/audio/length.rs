@@ -0,0 +1,68 @@
+/// Length counter, silences a channel after a set time
+///
+/// Clocked at 256 Hz by the frame sequencer.
+pub struct Length {
+	/// Counter is decremented, NRx4 bit 6
+	enabled: bool,
+	counter: u16,
+	/// 64 for most channels, 256 for the wave channel
+	max: u16,
+}
+
+impl Length {
+	pub fn new(max: u16) -> Self {
+		Self {
+			enabled: false,
+			counter: 0,
+			max,
+		}
+	}
+
+	/// Loads the length from NRx1
+	pub fn load(&mut self, val: u8) {
+		self.counter = self.max - (val as u16 & (self.max - 1));
+	}
+
+	/// Writes the enable bit of NRx4, returns true if the channel must stop
+	///
+	/// `length_step` tells whether the next frame sequencer step clocks
+	/// length. If it doesn't, enabling the counter clocks it once right away.
+	pub fn set_enabled(&mut self, enabled: bool, length_step: bool) -> bool {
+		let was_enabled = self.enabled;
+		self.enabled = enabled;
+
+		if !was_enabled && enabled && !length_step && self.counter > 0 {
+			self.counter -= 1;
+			return self.counter == 0;
+		}
+
+		false
+	}
+
+	/// A triggered channel with an expired length starts over at the maximum
+	pub fn trigger(&mut self, length_step: bool) {
+		if self.counter == 0 {
+			self.counter = self.max;
+
+			// Same extra clock as when enabling
+			if self.enabled && !length_step {
+				self.counter -= 1;
+			}
+		}
+	}
+
+	/// Clocks left until the channel stops
+	pub fn counter(&self) -> u16 {
+		self.counter
+	}
+
+	/// Returns true when the length runs out and the channel must stop
+	pub fn clock(&mut self) -> bool {
+		if self.enabled && self.counter > 0 {
+			self.counter -= 1;
+			return self.counter == 0;
+		}
+
+		false
+	}
+}
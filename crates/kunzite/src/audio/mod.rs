@@ -0,0 +1,635 @@
+//! Audio Processing Unit
+//!
+//! Sound Channel 1 - Tone & Sweep
+//! Sound Channel 2 - Tone
+//! Sound Channel 3 - Wave Output
+//! Sound Channel 4 - Noise
+
+mod blip;
+mod envelope;
+mod length;
+mod noise;
+mod output;
+mod square;
+mod wave;
+
+use self::{noise::Noise, output::Output, square::Square, wave::Wave};
+use crate::vgm::VgmLog;
+
+/// Clocks per second the APU runs at
+pub const CLOCK_RATE: u32 = 4_194_304;
+/// Output sample rate until the frontend picks one
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+/// Number of sound channels, each can have its own stem
+pub const CHANNELS: usize = 4;
+/// Samples kept per channel for the oscilloscope
+pub const SCOPE_LEN: usize = 512;
+
+/// Clocks between oscilloscope samples, 32768 Hz
+const SCOPE_PERIOD: u32 = 128;
+
+/// Clocks between steps of the 512 Hz frame sequencer
+const FRAME_SEQUENCER_PERIOD: u32 = CLOCK_RATE / 512;
+/// Clocks between reads of the band-limited buffers
+const BLIP_FRAME: u32 = 8192;
+/// Most the sample rate is bent by dynamic rate control, 0.5%
+const MAX_RATE_DELTA: f64 = 0.005;
+
+/// Bits of FF10-FF2F that always read back as 1
+///
+/// Covers write-only bits like the frequencies and unused registers.
+const READ_MASKS: [u8; 0x20] = [
+	0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+	0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+	0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+	0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+	0x00, 0x00, 0x70, // NR50-NR52
+	0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // unused
+];
+
+/// State of a channel as shown in the debugger
+pub struct ChannelInfo {
+	/// Playing, as reported in NR52
+	pub enabled: bool,
+	/// Tone in Hz, the LFSR clock rate for noise
+	pub frequency: f32,
+	/// Duty cycle of the square channels, 0-3
+	pub duty: Option<u8>,
+	/// Envelope volume 0-15, the output level 0-3 for the wave channel
+	pub volume: u8,
+	/// Length counter
+	pub length: u16,
+}
+
+pub struct Audio {
+	square1: Square,
+	square2: Square,
+	wave: Wave,
+	noise: Noise,
+
+	/// Last values written to FF10-FF26
+	regs: [u8; 0x17],
+	/// Channel control / ON-OFF / Volume (R/W) [FF24]
+	channel_control: u8,
+	/// Selection of Sound output terminal (R/W) [FF25]
+	output_select: u8,
+	/// Sound on/off [FF26 bit 7]
+	enabled: bool,
+	/// CGB keeps length counters from being written while off
+	cgb: bool,
+
+	/// Clocks until the next frame sequencer step
+	sequencer_timer: u32,
+	/// Frame sequencer step, 0-7
+	sequencer_step: u8,
+
+	sample_rate: u32,
+	/// Factor applied to the sample rate by dynamic rate control, takes
+	/// effect at the end of the outputs' frame
+	rate_adjust: f64,
+	/// The mix of all channels
+	output: Output,
+	/// Each channel on its own, only produced when asked for
+	stems: Option<Vec<Output>>,
+	/// Clocks since the outputs' last frame
+	clock: u32,
+
+	/// Channels left out of the mix
+	muted: [bool; CHANNELS],
+	/// Once any channel is soloed only soloed ones are mixed
+	solo: [bool; CHANNELS],
+	/// Recent output of each channel, a ring buffer
+	scope: [[f32; SCOPE_LEN]; CHANNELS],
+	/// Next sample to be written in `scope`
+	scope_pos: usize,
+	scope_timer: u32,
+
+	/// Register writes are logged here while recording a VGM
+	vgm: Option<VgmLog>,
+}
+
+impl Audio {
+	pub fn new() -> Self {
+		Self {
+			square1: Square::new(true),
+			square2: Square::new(false),
+			wave: Wave::new(),
+			noise: Noise::new(),
+			regs: [0; 0x17],
+			channel_control: 0,
+			output_select: 0,
+			enabled: false,
+			cgb: false,
+			sequencer_timer: FRAME_SEQUENCER_PERIOD,
+			sequencer_step: 0,
+			sample_rate: DEFAULT_SAMPLE_RATE,
+			rate_adjust: 1.0,
+			output: Output::new(false, DEFAULT_SAMPLE_RATE),
+			stems: None,
+			clock: 0,
+			muted: [false; CHANNELS],
+			solo: [false; CHANNELS],
+			scope: [[0.0; SCOPE_LEN]; CHANNELS],
+			scope_pos: 0,
+			scope_timer: SCOPE_PERIOD,
+			vgm: None,
+		}
+	}
+
+	pub fn set_cgb(&mut self, cgb: bool) {
+		self.cgb = cgb;
+		self.reset_outputs();
+	}
+
+	/// Sets the rate samples are produced at
+	pub fn set_sample_rate(&mut self, rate: u32) {
+		self.sample_rate = rate;
+		self.reset_outputs();
+	}
+
+	/// Produces a stem for every channel alongside the mix
+	pub fn set_stems(&mut self, stems: bool) {
+		self.stems = if stems {
+			let mut outputs: Vec<_> = (0..CHANNELS)
+				.map(|_| Output::new(self.cgb, self.sample_rate))
+				.collect();
+			for output in &mut outputs {
+				output.set_rate(self.sample_rate as f64 * self.rate_adjust);
+			}
+			Some(outputs)
+		} else {
+			None
+		};
+	}
+
+	/// Starts the outputs over, the filters depend on model and rate
+	fn reset_outputs(&mut self) {
+		self.rate_adjust = 1.0;
+		self.clock = 0;
+		self.output = Output::new(self.cgb, self.sample_rate);
+		if let Some(stems) = &mut self.stems {
+			for stem in stems {
+				*stem = Output::new(self.cgb, self.sample_rate);
+			}
+		}
+	}
+
+	/// Dynamic rate control, keeps the frontend's buffer from running dry
+	/// or overflowing when frames aren't paced exactly
+	///
+	/// `fill` goes from 0.0 for an empty buffer to 1.0 for a full one, the
+	/// sample rate is bent slightly to steer it back towards half full.
+	pub fn adjust_rate(&mut self, fill: f64) {
+		let fill = fill.max(0.0).min(1.0);
+		self.rate_adjust = 1.0 + MAX_RATE_DELTA * (1.0 - 2.0 * fill);
+	}
+
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	/// Takes the samples produced so far, interleaved left and right
+	///
+	/// The frontend should drain them at least once a second, older samples
+	/// are dropped otherwise.
+	pub fn take_samples(&mut self) -> Vec<f32> {
+		self.output.take_samples()
+	}
+
+	/// Takes the samples of each channel's stem, if enabled
+	///
+	/// Stems are laid out like the mix, together they add up to it.
+	pub fn take_stems(&mut self) -> Option<Vec<Vec<f32>>> {
+		let stems = self.stems.as_mut()?;
+		Some(stems.iter_mut().map(Output::take_samples).collect())
+	}
+
+	pub fn channel_info(&self, channel: usize) -> ChannelInfo {
+		match channel {
+			0 => self.square1.info(),
+			1 => self.square2.info(),
+			2 => self.wave.info(),
+			3 => self.noise.info(),
+			_ => panic!("No sound channel {}", channel),
+		}
+	}
+
+	pub fn muted(&self, channel: usize) -> bool {
+		self.muted[channel]
+	}
+
+	/// Leaves a channel out of the mix, stems are not affected
+	pub fn set_muted(&mut self, channel: usize, muted: bool) {
+		self.muted[channel] = muted;
+	}
+
+	pub fn solo(&self, channel: usize) -> bool {
+		self.solo[channel]
+	}
+
+	/// Mixes only the soloed channels, stems are not affected
+	pub fn set_solo(&mut self, channel: usize, solo: bool) {
+		self.solo[channel] = solo;
+	}
+
+	/// Whether a channel makes it into the mix
+	fn audible(&self, channel: usize) -> bool {
+		let soloing = self.solo.iter().any(|&solo| solo);
+		!self.muted[channel] && (!soloing || self.solo[channel])
+	}
+
+	/// Recent output of a channel from oldest to newest, -1.0 to 1.0
+	pub fn scope(&self, channel: usize) -> Vec<f32> {
+		let (newer, older) = self.scope[channel].split_at(self.scope_pos);
+		older.iter().chain(newer).copied().collect()
+	}
+
+	/// Starts logging register writes
+	///
+	/// The log begins with the current register values so it plays back from
+	/// here. Channels that are playing come in with their next trigger.
+	pub fn start_vgm(&mut self) {
+		let mut log = VgmLog::new();
+
+		log.write(0xFF26, (self.enabled as u8) << 7);
+		if self.enabled {
+			// Wave RAM is only written reliably with channel 3 off
+			log.write(0xFF1A, 0x00);
+			for (i, &val) in self.wave.ram().iter().enumerate() {
+				log.write(0xFF30 + i, val);
+			}
+
+			for addr in 0xFF10..=0xFF25 {
+				let val = self.regs[addr - 0xFF10];
+				// Leave out the trigger bits of NRx4
+				let val = match addr {
+					0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => val & 0x7F,
+					_ => val,
+				};
+				log.write(addr, val);
+			}
+		}
+
+		self.vgm = Some(log);
+	}
+
+	/// Stops logging and hands over the log
+	pub fn stop_vgm(&mut self) -> Option<VgmLog> {
+		self.vgm.take()
+	}
+
+	pub fn vgm(&self) -> Option<&VgmLog> {
+		self.vgm.as_ref()
+	}
+
+	/// Runs the frame sequencer for one step
+	fn step_sequencer(&mut self) {
+		// Length at 256 Hz
+		if self.sequencer_step % 2 == 0 {
+			self.square1.clock_length();
+			self.square2.clock_length();
+			self.wave.clock_length();
+			self.noise.clock_length();
+		}
+
+		// Sweep at 128 Hz
+		if self.sequencer_step == 2 || self.sequencer_step == 6 {
+			self.square1.clock_sweep();
+		}
+
+		// Envelope at 64 Hz
+		if self.sequencer_step == 7 {
+			self.square1.clock_envelope();
+			self.square2.clock_envelope();
+			self.noise.clock_envelope();
+		}
+
+		self.sequencer_step = (self.sequencer_step + 1) & 0x7;
+	}
+
+	/// Writes NR52, turning the APU off clears every register
+	fn write_power(&mut self, val: u8) {
+		let enabled = val & 0x80 > 0;
+
+		if self.enabled && !enabled {
+			// Only the DMG keeps the length counters
+			let keep_length = !self.cgb;
+			self.square1.reset(keep_length);
+			self.square2.reset(keep_length);
+			self.wave.reset(keep_length);
+			self.noise.reset(keep_length);
+
+			self.regs = [0; 0x17];
+			self.channel_control = 0;
+			self.output_select = 0;
+		} else if !self.enabled && enabled {
+			// The next step is the first one again
+			self.sequencer_timer = FRAME_SEQUENCER_PERIOD;
+			self.sequencer_step = 0;
+		}
+
+		self.enabled = enabled;
+	}
+
+	/// Writes to a register while the APU is off
+	///
+	/// Everything is ignored, except for the lengths on DMG.
+	fn write_powered_off(&mut self, addr: usize, val: u8) {
+		if self.cgb {
+			return;
+		}
+
+		match addr {
+			0xFF11 => self.square1.write_length(val),
+			0xFF16 => self.square2.write_length(val),
+			0xFF1B => self.wave.write_length(val),
+			0xFF20 => self.noise.write_length(val),
+			_ => (),
+		}
+	}
+
+	/// Output of each channel's DAC, 0.0 while it is off
+	fn analog(&self) -> [f32; CHANNELS] {
+		let mut analog = [0.0; CHANNELS];
+		if !self.enabled {
+			return analog;
+		}
+
+		let channels = [
+			(self.square1.dac_enabled(), self.square1.output()),
+			(self.square2.dac_enabled(), self.square2.output()),
+			(self.wave.dac_enabled(), self.wave.output()),
+			(self.noise.dac_enabled(), self.noise.output()),
+		];
+
+		for (out, &(dac, output)) in analog.iter_mut().zip(&channels) {
+			// The DACs map 0-15 onto 1.0 down to -1.0
+			if dac {
+				*out = 1.0 - output as f32 / 7.5;
+			}
+		}
+
+		analog
+	}
+
+	/// Left and right output of each channel, after panning and volume
+	fn mix_channels(&self, analog: &[f32; CHANNELS]) -> [(f32, f32); CHANNELS] {
+		let mut mixed = [(0.0, 0.0); CHANNELS];
+
+		// Master volume goes from 1/8 to 8/8, the channels share the range
+		let left_volume = (((self.channel_control >> 4) & 0x7) + 1) as f32 / 8.0 / 4.0;
+		let right_volume = ((self.channel_control & 0x7) + 1) as f32 / 8.0 / 4.0;
+
+		for (i, &analog) in analog.iter().enumerate() {
+			if self.output_select & (0x10 << i) > 0 {
+				mixed[i].0 = analog * left_volume;
+			}
+			if self.output_select & (0x01 << i) > 0 {
+				mixed[i].1 = analog * right_volume;
+			}
+		}
+
+		mixed
+	}
+
+	/// Feeds the current amplitudes to the outputs
+	fn add_amplitude(&mut self, analog: &[f32; CHANNELS]) {
+		let channels = self.mix_channels(analog);
+
+		let mut mix = (0.0, 0.0);
+		for (i, &(left, right)) in channels.iter().enumerate() {
+			if self.audible(i) {
+				mix = (mix.0 + left, mix.1 + right);
+			}
+		}
+		self.output.set_amplitude(self.clock, mix);
+
+		if let Some(stems) = &mut self.stems {
+			for (stem, &amplitude) in stems.iter_mut().zip(&channels) {
+				stem.set_amplitude(self.clock, amplitude);
+			}
+		}
+	}
+
+	/// Reads the finished samples out of the outputs
+	///
+	/// The rate only changes here, the steps of a frame are placed with the
+	/// rate it started with.
+	fn end_output_frame(&mut self) {
+		let rate = self.sample_rate as f64 * self.rate_adjust;
+
+		self.output.end_frame(self.clock);
+		self.output.set_rate(rate);
+		for stem in self.stems.iter_mut().flatten() {
+			stem.end_frame(self.clock);
+			stem.set_rate(rate);
+		}
+		self.clock = 0;
+	}
+}
+
+impl Audio {
+	pub fn write(&mut self, addr: usize, val: u8) {
+		if let Some(vgm) = &mut self.vgm {
+			vgm.write(addr, val);
+		}
+
+		if !self.enabled && addr < 0xFF26 {
+			self.write_powered_off(addr, val);
+			return;
+		}
+
+		if let 0xFF10..=0xFF25 = addr {
+			self.regs[addr - 0xFF10] = val;
+		}
+
+		// Length enables clock once more if the next step won't
+		let length_step = self.sequencer_step % 2 == 0;
+
+		match addr {
+			0xFF10..=0xFF14 => self.square1.write(addr - 0xFF10, val, length_step),
+			0xFF15 => (), // unused, NR20 doesn't exist
+			0xFF16..=0xFF19 => self.square2.write(addr - 0xFF15, val, length_step),
+			0xFF1A..=0xFF1E => self.wave.write(addr - 0xFF1A, val, length_step),
+			0xFF1F => (), // unused, NR40 doesn't exist
+			0xFF20..=0xFF23 => self.noise.write(addr - 0xFF1F, val, length_step),
+			0xFF24 => self.channel_control = val,
+			0xFF25 => self.output_select = val,
+			0xFF26 => self.write_power(val),
+			0xFF27..0xFF30 => (), // unused
+			0xFF30..0xFF40 => self.wave.write_ram(addr, val, self.cgb),
+			_ => unreachable!("Unexpected address: 0x{:04x}", addr),
+		}
+	}
+
+	pub fn read(&self, addr: usize) -> u8 {
+		match addr {
+			0xFF26 => {
+				let status = [
+					self.square1.enabled(),
+					self.square2.enabled(),
+					self.wave.enabled(),
+					self.noise.enabled(),
+				];
+				let channels = status
+					.iter()
+					.enumerate()
+					.fold(0, |acc, (i, &on)| acc | (on as u8) << i);
+
+				(self.enabled as u8) << 7 | READ_MASKS[0x16] | channels
+			}
+			0xFF10..0xFF26 => self.regs[addr - 0xFF10] | READ_MASKS[addr - 0xFF10],
+			0xFF27..0xFF30 => 0xFF, // unused
+			0xFF30..0xFF40 => self.wave.read_ram(addr, self.cgb),
+			_ => unreachable!("Unexpected address: 0x{:04x}", addr),
+		}
+	}
+
+	pub fn update(&mut self, tick: u8) {
+		let cycles = tick as u32;
+
+		if let Some(vgm) = &mut self.vgm {
+			vgm.advance(cycles);
+		}
+
+		if self.enabled {
+			self.square1.step(cycles);
+			self.square2.step(cycles);
+			self.wave.step(cycles);
+			self.noise.step(cycles);
+
+			self.sequencer_timer -= cycles.min(self.sequencer_timer);
+			if self.sequencer_timer == 0 {
+				self.sequencer_timer = FRAME_SEQUENCER_PERIOD;
+				self.step_sequencer();
+			}
+		}
+
+		// Samples are produced even while the APU is off so time keeps flowing
+		self.clock += cycles;
+		let analog = self.analog();
+		self.add_amplitude(&analog);
+		if self.clock >= BLIP_FRAME {
+			self.end_output_frame();
+		}
+
+		self.scope_timer -= cycles.min(self.scope_timer);
+		if self.scope_timer == 0 {
+			self.scope_timer = SCOPE_PERIOD;
+			for (scope, &analog) in self.scope.iter_mut().zip(&analog) {
+				scope[self.scope_pos] = analog;
+			}
+			self.scope_pos = (self.scope_pos + 1) % SCOPE_LEN;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Runs the APU for a number of frame sequencer steps
+	fn run_steps(audio: &mut Audio, steps: u32) {
+		for _ in 0..steps * FRAME_SEQUENCER_PERIOD / 4 {
+			audio.update(4);
+		}
+	}
+
+	#[test]
+	fn test_length_expires() {
+		let mut audio = Audio::new();
+		audio.write(0xFF26, 0x80);
+		audio.write(0xFF17, 0xF0);
+		// Length of 2, clocked every other step
+		audio.write(0xFF16, 62);
+		audio.write(0xFF19, 0xC0);
+		assert_eq!(audio.read(0xFF26) & 0x2, 0x2);
+
+		run_steps(&mut audio, 2);
+		assert_eq!(audio.read(0xFF26) & 0x2, 0x2);
+		run_steps(&mut audio, 2);
+		assert_eq!(audio.read(0xFF26) & 0x2, 0);
+	}
+
+	#[test]
+	fn test_sweep_overflow() {
+		let mut audio = Audio::new();
+		audio.write(0xFF26, 0x80);
+		audio.write(0xFF12, 0xF0);
+		// 0x7FF plus half of it overflows right on trigger
+		audio.write(0xFF10, 0x11);
+		audio.write(0xFF13, 0xFF);
+		audio.write(0xFF14, 0x87);
+		assert_eq!(audio.read(0xFF26) & 0x1, 0);
+	}
+
+	#[test]
+	fn test_read_masks() {
+		let mut audio = Audio::new();
+		audio.write(0xFF26, 0x80);
+		audio.write(0xFF13, 0x12);
+		audio.write(0xFF11, 0x80);
+		assert_eq!(audio.read(0xFF13), 0xFF);
+		assert_eq!(audio.read(0xFF11), 0xBF);
+		assert_eq!(audio.read(0xFF15), 0xFF);
+		assert_eq!(audio.read(0xFF27), 0xFF);
+	}
+
+	#[test]
+	fn test_power_off() {
+		let mut audio = Audio::new();
+		audio.write(0xFF26, 0x80);
+		audio.write(0xFF24, 0x77);
+		audio.write(0xFF26, 0x00);
+		assert_eq!(audio.read(0xFF24), 0x00);
+		assert_eq!(audio.read(0xFF26), 0x70);
+
+		// Writes are dropped until the APU is turned back on
+		audio.write(0xFF25, 0xFF);
+		assert_eq!(audio.read(0xFF25), 0x00);
+
+		// Wave RAM is not affected
+		audio.write(0xFF30, 0x5A);
+		assert_eq!(audio.read(0xFF30), 0x5A);
+	}
+
+	#[test]
+	fn test_high_pass() {
+		let mut audio = Audio::new();
+		audio.write(0xFF26, 0x80);
+		audio.write(0xFF25, 0x22);
+		// DAC on at volume 0, a constant offset
+		audio.write(0xFF17, 0x08);
+
+		run_steps(&mut audio, 512);
+		let samples = audio.take_samples();
+		// The step goes through, then decays back to silence
+		assert!(samples[..100].iter().any(|s| s.abs() > 0.01));
+		assert!(samples[samples.len() - 1].abs() < 0.001);
+	}
+
+	#[test]
+	fn test_solo() {
+		let mut audio = Audio::new();
+		audio.write(0xFF26, 0x80);
+		audio.write(0xFF25, 0x11);
+		audio.write(0xFF12, 0xF0);
+		audio.write(0xFF14, 0x80);
+
+		// Channel 1 plays, but only channel 2 is soloed
+		audio.set_solo(1, true);
+		run_steps(&mut audio, 8);
+		assert!(audio.take_samples().iter().all(|&s| s == 0.0));
+		assert!(audio.scope(0).iter().any(|&s| s != 0.0));
+	}
+
+	#[test]
+	fn test_sample_rate() {
+		let mut audio = Audio::new();
+		audio.set_sample_rate(32_768);
+		run_steps(&mut audio, 512);
+
+		// One second of stereo samples
+		assert_eq!(audio.take_samples().len(), 2 * 32_768);
+	}
+}
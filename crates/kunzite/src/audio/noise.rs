@@ -0,0 +1,142 @@
+use super::{envelope::Envelope, length::Length, ChannelInfo, CLOCK_RATE};
+
+/// Base divisors selected by NR43 bits 0-2
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Noise channel, driven by a linear feedback shift register
+pub struct Noise {
+	length: Length,
+	envelope: Envelope,
+	/// Clock shift, width and divisor, NR43
+	poly: u8,
+	timer: u32,
+	/// 15 bit LFSR, the output is the inverted lowest bit
+	lfsr: u16,
+	enabled: bool,
+}
+
+impl Noise {
+	pub fn new() -> Self {
+		let mut noise = Self {
+			length: Length::new(64),
+			envelope: Envelope::default(),
+			poly: 0,
+			timer: 0,
+			lfsr: 0x7FFF,
+			enabled: false,
+		};
+		noise.timer = noise.period();
+
+		noise
+	}
+
+	fn period(&self) -> u32 {
+		DIVISORS[(self.poly & 0x7) as usize] << (self.poly >> 4)
+	}
+
+	/// Writes NR41-NR44, `reg` is the offset of the register from NR40
+	///
+	/// `length_step` tells whether the next frame sequencer step clocks
+	/// length.
+	pub fn write(&mut self, reg: usize, val: u8, length_step: bool) {
+		match reg {
+			1 => self.length.load(val & 0x3F),
+			2 => {
+				self.envelope.write(val);
+				if !self.envelope.dac_enabled() {
+					self.enabled = false;
+				}
+			}
+			3 => self.poly = val,
+			4 => {
+				let expired = self.length.set_enabled(val & 0x40 > 0, length_step);
+				if val & 0x80 > 0 {
+					self.trigger(length_step);
+				} else if expired {
+					self.enabled = false;
+				}
+			}
+			_ => unreachable!(),
+		}
+	}
+
+	/// Writes NR41 while the APU is off (DMG)
+	pub fn write_length(&mut self, val: u8) {
+		self.length.load(val & 0x3F);
+	}
+
+	/// Returns the channel to its power on state, optionally keeping the
+	/// length counter
+	pub fn reset(&mut self, keep_length: bool) {
+		let length = std::mem::replace(&mut self.length, Length::new(64));
+		*self = Self::new();
+
+		if keep_length {
+			self.length = length;
+		}
+	}
+
+	fn trigger(&mut self, length_step: bool) {
+		self.enabled = self.envelope.dac_enabled();
+		self.length.trigger(length_step);
+		self.envelope.trigger();
+		self.timer = self.period();
+		self.lfsr = 0x7FFF;
+	}
+
+	pub fn enabled(&self) -> bool {
+		self.enabled
+	}
+
+	pub fn dac_enabled(&self) -> bool {
+		self.envelope.dac_enabled()
+	}
+
+	pub fn info(&self) -> ChannelInfo {
+		ChannelInfo {
+			enabled: self.enabled,
+			frequency: CLOCK_RATE as f32 / self.period() as f32,
+			duty: None,
+			volume: self.envelope.volume(),
+			length: self.length.counter(),
+		}
+	}
+
+	/// Current output, 0-15
+	pub fn output(&self) -> u8 {
+		if !self.enabled || self.lfsr & 1 > 0 {
+			return 0;
+		}
+
+		self.envelope.volume()
+	}
+
+	/// Shifts the LFSR once, in 7 bit mode bit 6 gets the feedback too
+	fn shift(&mut self) {
+		let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+		self.lfsr = (self.lfsr >> 1) | feedback << 14;
+
+		if self.poly & 0x08 > 0 {
+			self.lfsr = (self.lfsr & !0x40) | feedback << 6;
+		}
+	}
+
+	pub fn step(&mut self, mut cycles: u32) {
+		while cycles >= self.timer {
+			cycles -= self.timer;
+			self.timer = self.period();
+			self.shift();
+		}
+		self.timer -= cycles;
+	}
+
+	pub fn clock_length(&mut self) {
+		if self.length.clock() {
+			self.enabled = false;
+		}
+	}
+
+	pub fn clock_envelope(&mut self) {
+		self.envelope.clock();
+	}
+}
@@ -0,0 +1,80 @@
+use super::{
+	blip::{BlipBuf, HighPass},
+	CLOCK_RATE,
+};
+
+/// A stereo output, turns amplitude changes into filtered samples
+pub struct Output {
+	/// Left and right
+	blips: [BlipBuf; 2],
+	high_pass: [HighPass; 2],
+	/// Last amplitudes, changes are fed to the buffers
+	amplitude: (f32, f32),
+	/// Interleaved stereo samples waiting to be taken
+	samples: Vec<f32>,
+	/// Keep at most this many samples around
+	max_samples: usize,
+}
+
+impl Output {
+	pub fn new(cgb: bool, sample_rate: u32) -> Self {
+		let rate = sample_rate as f64;
+
+		Self {
+			blips: [
+				BlipBuf::new(CLOCK_RATE, rate),
+				BlipBuf::new(CLOCK_RATE, rate),
+			],
+			high_pass: [
+				HighPass::new(cgb, CLOCK_RATE, rate),
+				HighPass::new(cgb, CLOCK_RATE, rate),
+			],
+			amplitude: (0.0, 0.0),
+			samples: Vec::new(),
+			// A second of stereo
+			max_samples: sample_rate as usize * 2,
+		}
+	}
+
+	/// Bends the sample rate for dynamic rate control
+	pub fn set_rate(&mut self, rate: f64) {
+		for blip in &mut self.blips {
+			blip.set_rates(CLOCK_RATE, rate);
+		}
+	}
+
+	/// Sets the amplitude from `clock` clocks into the current frame on
+	pub fn set_amplitude(&mut self, clock: u32, (left, right): (f32, f32)) {
+		if left != self.amplitude.0 {
+			self.blips[0].add_delta(clock, left - self.amplitude.0);
+		}
+		if right != self.amplitude.1 {
+			self.blips[1].add_delta(clock, right - self.amplitude.1);
+		}
+		self.amplitude = (left, right);
+	}
+
+	/// Reads the finished samples of a frame of `clocks` out of the buffers
+	pub fn end_frame(&mut self, clocks: u32) {
+		let (mut left, mut right) = (Vec::new(), Vec::new());
+		self.blips[0].end_frame(clocks);
+		self.blips[1].end_frame(clocks);
+		self.blips[0].read_samples(&mut left);
+		self.blips[1].read_samples(&mut right);
+
+		for (l, r) in left.into_iter().zip(right) {
+			self.samples.push(self.high_pass[0].apply(l));
+			self.samples.push(self.high_pass[1].apply(r));
+		}
+
+		// Nobody is listening, drop the oldest
+		if self.samples.len() > self.max_samples {
+			let excess = self.samples.len() - self.max_samples;
+			self.samples.drain(..excess);
+		}
+	}
+
+	pub fn take_samples(&mut self) -> Vec<f32> {
+		std::mem::take(&mut self.samples)
+	}
+}
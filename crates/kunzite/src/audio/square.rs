@@ -0,0 +1,246 @@
+use super::{envelope::Envelope, length::Length, ChannelInfo};
+
+/// Waveforms for the four duty cycles: 12.5%, 25%, 50% and 75%
+const DUTY: [[u8; 8]; 4] = [
+	[0, 0, 0, 0, 0, 0, 0, 1],
+	[1, 0, 0, 0, 0, 0, 0, 1],
+	[1, 0, 0, 0, 0, 1, 1, 1],
+	[0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+/// Frequency sweep, only on channel 1
+#[derive(Default)]
+struct Sweep {
+	/// Period, direction and shift, NR10
+	reg: u8,
+	enabled: bool,
+	/// Copy of the frequency the sweep works on
+	shadow: u16,
+	timer: u8,
+	/// A subtraction happened since the last trigger
+	negated: bool,
+}
+
+impl Sweep {
+	fn period(&self) -> u8 {
+		(self.reg >> 4) & 0x7
+	}
+
+	fn shift(&self) -> u8 {
+		self.reg & 0x7
+	}
+
+	fn negate(&self) -> bool {
+		self.reg & 0x08 > 0
+	}
+
+	/// Next frequency, anything above 2047 silences the channel
+	fn next(&mut self) -> u16 {
+		let delta = self.shadow >> self.shift();
+
+		if self.negate() {
+			self.negated = true;
+			self.shadow - delta
+		} else {
+			self.shadow + delta
+		}
+	}
+
+	/// A period of 0 is treated as 8
+	fn reload(&mut self) {
+		self.timer = match self.period() {
+			0 => 8,
+			period => period,
+		};
+	}
+}
+
+/// Square wave channel, channel 1 with a sweep and channel 2 without
+pub struct Square {
+	sweep: Option<Sweep>,
+	/// Wave pattern duty, NRx1 bits 6-7
+	duty: u8,
+	length: Length,
+	envelope: Envelope,
+	/// 11 bit frequency, NRx3 and NRx4 bits 0-2
+	frequency: u16,
+	/// Clocks until the next step through the waveform
+	timer: u32,
+	/// Position within the waveform, 0-7
+	position: u8,
+	enabled: bool,
+}
+
+impl Square {
+	pub fn new(sweep: bool) -> Self {
+		let mut square = Self {
+			sweep: if sweep { Some(Sweep::default()) } else { None },
+			duty: 0,
+			length: Length::new(64),
+			envelope: Envelope::default(),
+			frequency: 0,
+			timer: 0,
+			position: 0,
+			enabled: false,
+		};
+		square.timer = square.period();
+
+		square
+	}
+
+	fn period(&self) -> u32 {
+		(2048 - self.frequency as u32) * 4
+	}
+
+	/// Writes NRx0-NRx4, `reg` is the offset of the register
+	///
+	/// `length_step` tells whether the next frame sequencer step clocks
+	/// length.
+	pub fn write(&mut self, reg: usize, val: u8, length_step: bool) {
+		match reg {
+			0 => {
+				if let Some(sweep) = &mut self.sweep {
+					sweep.reg = val;
+
+					// Leaving negate mode after it was used kills the channel
+					if sweep.negated && !sweep.negate() {
+						self.enabled = false;
+					}
+				}
+			}
+			1 => {
+				self.duty = val >> 6;
+				self.length.load(val & 0x3F);
+			}
+			2 => {
+				self.envelope.write(val);
+				if !self.envelope.dac_enabled() {
+					self.enabled = false;
+				}
+			}
+			3 => self.frequency = (self.frequency & 0x700) | val as u16,
+			4 => {
+				self.frequency = (self.frequency & 0xFF) | ((val & 0x7) as u16) << 8;
+				let expired = self.length.set_enabled(val & 0x40 > 0, length_step);
+				if val & 0x80 > 0 {
+					self.trigger(length_step);
+				} else if expired {
+					self.enabled = false;
+				}
+			}
+			_ => unreachable!(),
+		}
+	}
+
+	/// Writes NRx1 while the APU is off, only the length is changed (DMG)
+	pub fn write_length(&mut self, val: u8) {
+		self.length.load(val & 0x3F);
+	}
+
+	/// Returns the channel to its power on state, optionally keeping the
+	/// length counter
+	pub fn reset(&mut self, keep_length: bool) {
+		let length = std::mem::replace(&mut self.length, Length::new(64));
+		*self = Self::new(self.sweep.is_some());
+
+		if keep_length {
+			self.length = length;
+		}
+	}
+
+	fn trigger(&mut self, length_step: bool) {
+		self.enabled = self.envelope.dac_enabled();
+		self.length.trigger(length_step);
+		self.envelope.trigger();
+		self.timer = self.period();
+
+		let frequency = self.frequency;
+		if let Some(sweep) = &mut self.sweep {
+			sweep.shadow = frequency;
+			sweep.negated = false;
+			sweep.reload();
+			sweep.enabled = sweep.period() > 0 || sweep.shift() > 0;
+
+			// The overflow check runs right away when there is a shift
+			if sweep.shift() > 0 && sweep.next() > 2047 {
+				self.enabled = false;
+			}
+		}
+	}
+
+	pub fn enabled(&self) -> bool {
+		self.enabled
+	}
+
+	pub fn dac_enabled(&self) -> bool {
+		self.envelope.dac_enabled()
+	}
+
+	pub fn info(&self) -> ChannelInfo {
+		ChannelInfo {
+			enabled: self.enabled,
+			frequency: 131_072.0 / (2048 - self.frequency) as f32,
+			duty: Some(self.duty),
+			volume: self.envelope.volume(),
+			length: self.length.counter(),
+		}
+	}
+
+	/// Current output, 0-15
+	pub fn output(&self) -> u8 {
+		if !self.enabled {
+			return 0;
+		}
+
+		DUTY[self.duty as usize][self.position as usize] * self.envelope.volume()
+	}
+
+	pub fn step(&mut self, mut cycles: u32) {
+		while cycles >= self.timer {
+			cycles -= self.timer;
+			self.timer = self.period();
+			self.position = (self.position + 1) & 0x7;
+		}
+		self.timer -= cycles;
+	}
+
+	pub fn clock_length(&mut self) {
+		if self.length.clock() {
+			self.enabled = false;
+		}
+	}
+
+	pub fn clock_envelope(&mut self) {
+		self.envelope.clock();
+	}
+
+	pub fn clock_sweep(&mut self) {
+		let sweep = match &mut self.sweep {
+			Some(sweep) => sweep,
+			None => return,
+		};
+
+		sweep.timer = sweep.timer.saturating_sub(1);
+		if sweep.timer > 0 {
+			return;
+		}
+		sweep.reload();
+
+		if !sweep.enabled || sweep.period() == 0 {
+			return;
+		}
+
+		let next = sweep.next();
+		if next > 2047 {
+			self.enabled = false;
+		} else if sweep.shift() > 0 {
+			sweep.shadow = next;
+			self.frequency = next;
+
+			// The new frequency is checked for overflow once more
+			if sweep.next() > 2047 {
+				self.enabled = false;
+			}
+		}
+	}
+}
@@ -0,0 +1,187 @@
+use super::{length::Length, ChannelInfo};
+
+/// Wave channel, plays back 32 4-bit samples from wave RAM
+pub struct Wave {
+	/// DAC power, NR30 bit 7
+	dac: bool,
+	length: Length,
+	/// Output level, NR32 bits 5-6
+	level: u8,
+	/// 11 bit frequency, NR33 and NR34 bits 0-2
+	frequency: u16,
+	timer: u32,
+	/// Sample being played, 0-31
+	position: u8,
+	enabled: bool,
+	/// Clocks since the last sample was fetched from wave RAM
+	since_fetch: u32,
+	/// FF30-FF3F - Wave Pattern RAM, high nibble first
+	wave_ram: [u8; 0x10],
+}
+
+impl Wave {
+	pub fn new() -> Self {
+		let mut wave = Self {
+			dac: false,
+			length: Length::new(256),
+			level: 0,
+			frequency: 0,
+			timer: 0,
+			position: 0,
+			enabled: false,
+			since_fetch: 0,
+			wave_ram: [0; 0x10],
+		};
+		wave.timer = wave.period();
+
+		wave
+	}
+
+	fn period(&self) -> u32 {
+		(2048 - self.frequency as u32) * 2
+	}
+
+	/// Writes NR30-NR34, `reg` is the offset of the register
+	///
+	/// `length_step` tells whether the next frame sequencer step clocks
+	/// length.
+	pub fn write(&mut self, reg: usize, val: u8, length_step: bool) {
+		match reg {
+			0 => {
+				self.dac = val & 0x80 > 0;
+				if !self.dac {
+					self.enabled = false;
+				}
+			}
+			1 => self.length.load(val),
+			2 => self.level = (val >> 5) & 0x3,
+			3 => self.frequency = (self.frequency & 0x700) | val as u16,
+			4 => {
+				self.frequency = (self.frequency & 0xFF) | ((val & 0x7) as u16) << 8;
+				let expired = self.length.set_enabled(val & 0x40 > 0, length_step);
+				if val & 0x80 > 0 {
+					self.trigger(length_step);
+				} else if expired {
+					self.enabled = false;
+				}
+			}
+			_ => unreachable!(),
+		}
+	}
+
+	/// Writes NR31 while the APU is off (DMG)
+	pub fn write_length(&mut self, val: u8) {
+		self.length.load(val);
+	}
+
+	/// Returns the channel to its power on state, wave RAM is left alone
+	pub fn reset(&mut self, keep_length: bool) {
+		let length = std::mem::replace(&mut self.length, Length::new(256));
+		let wave_ram = self.wave_ram;
+		*self = Self::new();
+
+		self.wave_ram = wave_ram;
+		if keep_length {
+			self.length = length;
+		}
+	}
+
+	/// Wave RAM as is, without the access restrictions
+	pub fn ram(&self) -> &[u8; 0x10] {
+		&self.wave_ram
+	}
+
+	/// Byte of wave RAM the CPU actually reaches at `addr`
+	///
+	/// While the channel plays, accesses go to the byte being played
+	/// instead. On DMG that only works in the same cycle the channel reads
+	/// it, approximated here as the same M-cycle, and fails otherwise.
+	fn ram_index(&self, addr: usize, cgb: bool) -> Option<usize> {
+		if !self.enabled {
+			Some(addr & 0xF)
+		} else if cgb || self.since_fetch < 4 {
+			Some((self.position >> 1) as usize)
+		} else {
+			None
+		}
+	}
+
+	pub fn read_ram(&self, addr: usize, cgb: bool) -> u8 {
+		self.ram_index(addr, cgb)
+			.map_or(0xFF, |index| self.wave_ram[index])
+	}
+
+	pub fn write_ram(&mut self, addr: usize, val: u8, cgb: bool) {
+		if let Some(index) = self.ram_index(addr, cgb) {
+			self.wave_ram[index] = val;
+		}
+	}
+
+	fn trigger(&mut self, length_step: bool) {
+		self.enabled = self.dac;
+		self.length.trigger(length_step);
+		self.timer = self.period();
+		self.position = 0;
+	}
+
+	pub fn enabled(&self) -> bool {
+		self.enabled
+	}
+
+	pub fn dac_enabled(&self) -> bool {
+		self.dac
+	}
+
+	pub fn info(&self) -> ChannelInfo {
+		ChannelInfo {
+			enabled: self.enabled,
+			frequency: 65_536.0 / (2048 - self.frequency) as f32,
+			duty: None,
+			volume: self.level,
+			length: self.length.counter(),
+		}
+	}
+
+	/// Current output, 0-15
+	pub fn output(&self) -> u8 {
+		if !self.enabled {
+			return 0;
+		}
+
+		let byte = self.wave_ram[(self.position >> 1) as usize];
+		let sample = if self.position & 1 == 0 {
+			byte >> 4
+		} else {
+			byte & 0xF
+		};
+
+		// Mute, 100%, 50% and 25%
+		match self.level {
+			0 => 0,
+			level => sample >> (level - 1),
+		}
+	}
+
+	pub fn step(&mut self, mut cycles: u32) {
+		let mut fetched = false;
+		while cycles >= self.timer {
+			cycles -= self.timer;
+			self.timer = self.period();
+			self.position = (self.position + 1) & 0x1F;
+			fetched = true;
+		}
+		self.timer -= cycles;
+
+		self.since_fetch = if fetched {
+			cycles
+		} else {
+			self.since_fetch.saturating_add(cycles)
+		};
+	}
+
+	pub fn clock_length(&mut self) {
+		if self.length.clock() {
+			self.enabled = false;
+		}
+	}
+}
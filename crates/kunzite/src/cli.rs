@@ -0,0 +1,276 @@
+//! Command line arguments
+
+use crate::serial::Connection;
+use color_eyre::{eyre::eyre, Result};
+use std::path::PathBuf;
+
+/// ROM that is loaded when none is given
+const DEFAULT_ROM: &str = "roms/dmg-acid2.gb";
+
+const USAGE: &str = "usage: kunzite [ROM|MUSIC.gbs [--track N]]
+               [--screenshot-at-frame N OUT.png] [--scale N]
+               [--record OUT.gif|OUT.y4m --frames N]
+               [--record-audio OUT.wav [--stems] --frames N]
+               [--record-vgm OUT.vgm --frames N]
+               [--benchmark --frames N]
+               [--serial none|loopback|stdout|printer [--printer-dir DIR]
+                | --link ROM
+                | --link-listen [HOST:]PORT | --link-connect HOST:PORT]";
+
+/// Parsed command line
+#[derive(Debug, PartialEq)]
+pub struct Args {
+	pub rom: PathBuf,
+	/// Frame to take a screenshot at and where to save it, runs headless
+	pub screenshot: Option<(u64, PathBuf)>,
+	/// Integer scale of saved screenshots
+	pub scale: usize,
+	/// Video file to record to, runs headless
+	pub record: Option<PathBuf>,
+	/// WAV file to record the sound to, runs headless
+	pub record_audio: Option<PathBuf>,
+	/// Also record each sound channel to its own WAV file
+	pub stems: bool,
+	/// VGM file to log the sound register writes to, runs headless
+	pub record_vgm: Option<PathBuf>,
+	/// Report how fast the frames ran, runs headless
+	pub benchmark: bool,
+	/// Number of frames to run for when recording or benchmarking
+	pub frames: u64,
+	/// Song of a GBS file to play, 1 based
+	pub track: Option<u8>,
+	/// What is plugged into the serial port
+	pub serial: Connection,
+	/// Game for a second Game Boy on the other end of the link cable
+	pub link: Option<PathBuf>,
+}
+
+impl Args {
+	/// Parses the arguments of the current process
+	pub fn from_env() -> Result<Self> {
+		Self::parse(std::env::args().skip(1))
+	}
+
+	pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+		let mut args = args.into_iter();
+		let mut printer_dir = None;
+		let mut parsed = Self {
+			rom: PathBuf::from(DEFAULT_ROM),
+			screenshot: None,
+			scale: 1,
+			record: None,
+			record_audio: None,
+			stems: false,
+			record_vgm: None,
+			benchmark: false,
+			frames: 0,
+			track: None,
+			serial: Connection::None,
+			link: None,
+		};
+
+		while let Some(arg) = args.next() {
+			match arg.as_str() {
+				"--screenshot-at-frame" => {
+					let frame = number(&arg, args.next())?;
+					let path = value(&arg, args.next())?;
+					parsed.screenshot = Some((frame, path.into()));
+				}
+				"--scale" => {
+					parsed.scale = number(&arg, args.next())?;
+					if parsed.scale == 0 {
+						return Err(eyre!("--scale must be at least 1"));
+					}
+				}
+				"--record" => parsed.record = Some(value(&arg, args.next())?.into()),
+				"--record-audio" => parsed.record_audio = Some(value(&arg, args.next())?.into()),
+				"--stems" => parsed.stems = true,
+				"--record-vgm" => parsed.record_vgm = Some(value(&arg, args.next())?.into()),
+				"--benchmark" => parsed.benchmark = true,
+				"--frames" => parsed.frames = number(&arg, args.next())?,
+				"--track" => parsed.track = Some(number(&arg, args.next())?),
+				"--serial" => serial(&mut parsed, value(&arg, args.next())?.parse()?)?,
+				"--printer-dir" => printer_dir = Some(value(&arg, args.next())?),
+				"--link-listen" => serial(&mut parsed, listen_addr(value(&arg, args.next())?))?,
+				"--link-connect" => {
+					serial(&mut parsed, Connection::Connect(value(&arg, args.next())?))?
+				}
+				"--link" => parsed.link = Some(value(&arg, args.next())?.into()),
+				_ if arg.starts_with("--") => {
+					return Err(eyre!("Unknown option {}\n{}", arg, USAGE));
+				}
+				_ => parsed.rom = PathBuf::from(arg),
+			}
+		}
+
+		if parsed.record.is_some() && parsed.frames == 0 {
+			return Err(eyre!("--record needs --frames\n{}", USAGE));
+		}
+		if parsed.record_audio.is_some() && parsed.frames == 0 {
+			return Err(eyre!("--record-audio needs --frames\n{}", USAGE));
+		}
+		if parsed.record_vgm.is_some() && parsed.frames == 0 {
+			return Err(eyre!("--record-vgm needs --frames\n{}", USAGE));
+		}
+		if parsed.benchmark && parsed.frames == 0 {
+			return Err(eyre!("--benchmark needs --frames\n{}", USAGE));
+		}
+		if parsed.stems && parsed.record_audio.is_none() {
+			return Err(eyre!("--stems needs --record-audio\n{}", USAGE));
+		}
+		if parsed.track.is_some() && !parsed.gbs() {
+			return Err(eyre!("--track needs a GBS file\n{}", USAGE));
+		}
+		if let Some(dir) = printer_dir {
+			match &mut parsed.serial {
+				Connection::Printer(path) => *path = dir.into(),
+				_ => return Err(eyre!("--printer-dir needs --serial printer\n{}", USAGE)),
+			}
+		}
+		if parsed.link.is_some() && (parsed.gbs() || parsed.serial != Connection::None) {
+			return Err(eyre!(
+				"--link can't be used with a GBS file or another serial device\n{}",
+				USAGE
+			));
+		}
+
+		Ok(parsed)
+	}
+
+	/// Whether the ROM is a GBS music file to play
+	pub fn gbs(&self) -> bool {
+		let ext = self.rom.extension().and_then(|ext| ext.to_str());
+		ext.map_or(false, |ext| ext.eq_ignore_ascii_case("gbs"))
+	}
+
+	/// Whether to run without opening a window
+	pub fn headless(&self) -> bool {
+		self.screenshot.is_some()
+			|| self.record.is_some()
+			|| self.record_audio.is_some()
+			|| self.record_vgm.is_some()
+			|| self.benchmark
+	}
+}
+
+/// Sets what is plugged into the serial port, only one thing fits
+fn serial(args: &mut Args, connection: Connection) -> Result<()> {
+	if args.serial != Connection::None {
+		return Err(eyre!("Only one serial device can be connected\n{}", USAGE));
+	}
+	args.serial = connection;
+
+	Ok(())
+}
+
+/// Takes the value of an option
+fn value(option: &str, val: Option<String>) -> Result<String> {
+	val.ok_or_else(|| eyre!("Missing value for {}\n{}", option, USAGE))
+}
+
+/// Takes the value of an option that has to be a number
+fn number<T: std::str::FromStr>(option: &str, val: Option<String>) -> Result<T> {
+	let val = value(option, val)?;
+
+	val.parse()
+		.map_err(|_| eyre!("Expected a number for {}, got {}", option, val))
+}
+
+/// Where to listen for a link, a bare port only accepts connections from
+/// this machine
+fn listen_addr(addr: String) -> Connection {
+	if addr.parse::<u16>().is_ok() {
+		Connection::Listen(format!("127.0.0.1:{}", addr))
+	} else {
+		Connection::Listen(addr)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(args: &[&str]) -> Result<Args> {
+		Args::parse(args.iter().map(|arg| arg.to_string()))
+	}
+
+	#[test]
+	fn test_screenshot() {
+		let args = parse(&["game.gb", "--screenshot-at-frame", "60", "out.png"]);
+		assert_eq!(args.unwrap().screenshot, Some((60, "out.png".into())));
+
+		let args = parse(&["--scale", "3", "--screenshot-at-frame", "1", "a.png"]);
+		assert_eq!(args.unwrap(), Args {
+			rom: DEFAULT_ROM.into(),
+			screenshot: Some((1, "a.png".into())),
+			scale: 3,
+			record: None,
+			record_audio: None,
+			stems: false,
+			record_vgm: None,
+			benchmark: false,
+			frames: 0,
+			track: None,
+			serial: Connection::None,
+			link: None,
+		});
+		assert!(parse(&["--screenshot-at-frame", "60"]).is_err());
+		assert!(parse(&["--screenshot-at-frame", "soon", "out.png"]).is_err());
+		assert!(parse(&["--bogus"]).is_err());
+		assert!(parse(&["--record", "out.gif"]).is_err());
+	}
+
+	#[test]
+	fn test_record_audio() {
+		let args = parse(&["--record-audio", "out.wav", "--stems", "--frames", "3600"]).unwrap();
+		assert_eq!(args.record_audio, Some("out.wav".into()));
+		assert!(args.stems);
+		assert!(args.headless());
+
+		assert!(parse(&["--record-audio", "out.wav"]).is_err());
+		assert!(parse(&["--stems", "--frames", "60"]).is_err());
+		assert!(parse(&["--record-vgm", "out.vgm"]).is_err());
+	}
+
+	#[test]
+	fn test_benchmark() {
+		let args = parse(&["--benchmark", "--frames", "6000"]).unwrap();
+		assert!(args.benchmark);
+		assert!(args.headless());
+
+		assert!(parse(&["--benchmark"]).is_err());
+	}
+
+	#[test]
+	fn test_gbs() {
+		let args = parse(&["music.GBS", "--track", "4"]).unwrap();
+		assert!(args.gbs());
+		assert_eq!(args.track, Some(4));
+
+		assert!(parse(&["game.gb", "--track", "4"]).is_err());
+	}
+
+	#[test]
+	fn test_serial() {
+		let args = parse(&["--serial", "stdout"]).unwrap();
+		assert_eq!(args.serial, Connection::Stdout);
+		assert!(!args.headless());
+
+		assert!(parse(&["--serial", "modem"]).is_err());
+		assert!(parse(&["--printer-dir", "prints"]).is_err());
+		let args = parse(&["--printer-dir", "prints", "--serial", "printer"]).unwrap();
+		assert_eq!(args.serial, Connection::Printer("prints".into()));
+		assert!(parse(&["--serial", "stdout", "--serial", "loopback"]).is_err());
+
+		let args = parse(&["--link-connect", "localhost:8765"]).unwrap();
+		assert_eq!(args.serial, Connection::Connect("localhost:8765".into()));
+		let args = parse(&["--link-listen", "8765"]).unwrap();
+		assert_eq!(args.serial, Connection::Listen("127.0.0.1:8765".into()));
+		let args = parse(&["--link-listen", "0.0.0.0:8765"]).unwrap();
+		assert_eq!(args.serial, Connection::Listen("0.0.0.0:8765".into()));
+
+		let args = parse(&["red.gb", "--link", "blue.gb"]).unwrap();
+		assert_eq!(args.link, Some("blue.gb".into()));
+		assert!(parse(&["red.gb", "--link", "blue.gb", "--serial", "stdout"]).is_err());
+	}
+}
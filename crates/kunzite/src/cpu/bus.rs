@@ -0,0 +1,140 @@
+//! What the cpu is wired to
+//!
+//! The cpu only ever sees the address space through [`Bus`], so the SM83 core
+//! can run against any memory model. [`Memory`] is the whole Game Boy, while
+//! [`FlatBus`] is nothing but RAM, for tests and tools that want the cpu on
+//! its own.
+
+use crate::memory::Memory;
+
+/// Interrupt flag register
+const IF: usize = 0xFF0F;
+/// Interrupt enable register
+const IE: usize = 0xFFFF;
+
+/// The address space and everything else the cpu talks to
+pub trait Bus {
+	fn read(&self, addr: u16) -> u8;
+
+	fn write(&mut self, addr: u16, val: u8);
+
+	/// Runs everything besides the cpu for the given number of cpu T-cycles
+	fn tick(&mut self, cycles: u8);
+
+	/// Interrupts that are both requested and enabled, bit 0 (V-Blank) first
+	fn pending_interrupts(&self) -> u8;
+
+	/// Clears the request of interrupt `id` once its handler is called
+	fn acknowledge(&mut self, id: u8);
+
+	/// Whether a STOP instruction will switch speeds (CGB)
+	fn speed_switch_armed(&self) -> bool {
+		false
+	}
+
+	/// Toggles double speed mode, triggered by STOP (CGB)
+	fn switch_speed(&mut self) {}
+
+	/// Returns the T-cycles the cpu has to wait before continuing
+	fn take_stall(&mut self) -> u32 {
+		0
+	}
+
+	/// Whether a button is held on a selected joypad line, which ends STOP
+	fn joypad_input(&self) -> bool {
+		false
+	}
+}
+
+impl Bus for Memory {
+	fn read(&self, addr: u16) -> u8 {
+		Memory::read(self, addr)
+	}
+
+	fn write(&mut self, addr: u16, val: u8) {
+		Memory::write(self, addr, val)
+	}
+
+	fn tick(&mut self, cycles: u8) {
+		self.update(cycles);
+	}
+
+	fn pending_interrupts(&self) -> u8 {
+		self.int_flag & self.int_enable & 0x1F
+	}
+
+	fn acknowledge(&mut self, id: u8) {
+		self.int_flag &= !(1 << id);
+	}
+
+	fn speed_switch_armed(&self) -> bool {
+		Memory::speed_switch_armed(self)
+	}
+
+	fn switch_speed(&mut self) {
+		Memory::switch_speed(self)
+	}
+
+	fn take_stall(&mut self) -> u32 {
+		Memory::take_stall(self)
+	}
+
+	fn joypad_input(&self) -> bool {
+		self.joypad.read() & 0x0F != 0x0F
+	}
+}
+
+/// 64 KiB of RAM and nothing else
+///
+/// The interrupt registers are plain bytes at their usual addresses, so
+/// interrupts are requested by writing to IF.
+pub struct FlatBus {
+	pub ram: Box<[u8; 0x10000]>,
+	/// Cpu T-cycles run so far
+	pub cycles: u64,
+}
+
+impl FlatBus {
+	pub fn new() -> Self {
+		Self {
+			ram: Box::new([0; 0x10000]),
+			cycles: 0,
+		}
+	}
+
+	/// Copies `data` to memory starting at `addr`, whatever doesn't fit below
+	/// 0x10000 is left out
+	pub fn load(&mut self, addr: u16, data: &[u8]) {
+		let start = addr as usize;
+		let len = data.len().min(self.ram.len() - start);
+		self.ram[start..start + len].copy_from_slice(&data[..len]);
+	}
+}
+
+impl Default for FlatBus {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Bus for FlatBus {
+	fn read(&self, addr: u16) -> u8 {
+		self.ram[addr as usize]
+	}
+
+	fn write(&mut self, addr: u16, val: u8) {
+		self.ram[addr as usize] = val;
+	}
+
+	fn tick(&mut self, cycles: u8) {
+		self.cycles += cycles as u64;
+	}
+
+	fn pending_interrupts(&self) -> u8 {
+		self.ram[IF] & self.ram[IE] & 0x1F
+	}
+
+	fn acknowledge(&mut self, id: u8) {
+		self.ram[IF] &= !(1 << id);
+	}
+}
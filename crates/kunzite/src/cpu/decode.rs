@@ -1,6 +1,6 @@
 use super::{
 	instruction::{Flag, Instruction, Register16, Register8},
-	Cpu,
+	Bus, Cpu, FlatBus,
 };
 use std::fmt::Debug;
 
@@ -19,7 +19,7 @@ struct DecodeInfo {
 
 impl DecodeInfo {
 	#[allow(clippy::many_single_char_names)]
-	pub fn new(cpu: &Cpu, opcode: u8, prefix: Option<u8>) -> Self {
+	pub fn new<B: Bus>(cpu: &Cpu<B>, opcode: u8, prefix: Option<u8>) -> Self {
 		let rom = &cpu.memory;
 		let x = (opcode >> 6) & 0x3;
 		let y = (opcode >> 3) & 0x7;
@@ -27,8 +27,8 @@ impl DecodeInfo {
 		let p = y >> 1;
 		let q = y % 2;
 
-		let d = rom.read(cpu.pc + 1) as i8;
-		let n = rom.read(cpu.pc + 1);
+		let d = rom.read(cpu.pc.wrapping_add(1)) as i8;
+		let n = rom.read(cpu.pc.wrapping_add(1));
 		let nn = if cpu.pc as usize + 2 < 0x10000 {
 			((rom.read(cpu.pc + 2) as u16) << 8) | n as u16
 		} else {
@@ -86,48 +86,53 @@ const R: [Register8; 8] = [
 	Register8::A,
 ];
 
-impl Cpu {
+impl Cpu<FlatBus> {
 	/// Decodes a slice of a rom
 	/// effectively dissasembles a program
 	///
-	/// Will panic if an invalid opcode is read
-	///
-	/// TODO: have parsing return an option to avoid panics
+	/// Stops at the first invalid opcode, and at 0x10000 where the address
+	/// space ends, anything past that is ignored
 	pub fn try_decode_all(data: &[u8]) -> Vec<(u16, Instruction)> {
+		let data = &data[..data.len().min(0x10000)];
 		let mut instructions = vec![];
 		let mut this = Self::default();
+		this.memory.load(0, data);
 
-		let _ = this.memory.cartridge.rom = data.to_vec();
-
-		while let Some(inst) = this.parse_instruction() {
-			let pc = this.pc;
-			this.pc += inst.size();
-			instructions.push((pc, inst));
+		// Counted past 0xFFFF, where pc would wrap around
+		let mut addr = 0;
+		while addr < data.len() {
+			this.pc = addr as u16;
+			let inst = match this.parse_instruction() {
+				Some(inst) => inst,
+				None => break,
+			};
+			addr += inst.size() as usize;
+			instructions.push((this.pc, inst));
 		}
 
 		instructions
 	}
+}
 
+impl<B: Bus> Cpu<B> {
 	pub(crate) fn parse_instruction(&self) -> Option<Instruction> {
 		let rom = &self.memory;
+		let opcode = rom.read(self.pc);
+		let info = DecodeInfo::new(self, opcode, None);
 
-		if let Some(opcode) = rom.get(self.pc) {
-			let info = DecodeInfo::new(self, opcode, None);
-
-			#[cfg(feature = "debug_opcode")]
-			println!("{:?}", info);
+		#[cfg(feature = "debug_opcode")]
+		println!("{:?}", info);
 
-			let inst = match opcode {
-				0xCB => {
-					self.parse_cb_inst(DecodeInfo::new(self, rom.read(self.pc + 1), Some(0xCB)))
-				}
-				_ => self.parse_normal_inst(info),
-			};
+		let inst = match opcode {
+			0xCB => self.parse_cb_inst(DecodeInfo::new(
+				self,
+				rom.read(self.pc.wrapping_add(1)),
+				Some(0xCB),
+			)),
+			_ => self.parse_normal_inst(info),
+		};
 
-			Some(inst)
-		} else {
-			None
-		}
+		Some(inst)
 	}
 
 	fn parse_normal_inst(&self, info: DecodeInfo) -> Instruction {
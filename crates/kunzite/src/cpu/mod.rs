@@ -6,25 +6,31 @@ use crate::{
 	util::{lower, upper},
 };
 
+pub use self::bus::{Bus, FlatBus};
 use self::{instruction::Register8, register::Registers};
 
+mod bus;
 mod decode;
 pub mod instruction;
 mod register;
 
-/// The cpu
+/// The cpu, wired to the whole Game Boy unless told otherwise
 #[derive(Default)]
-pub struct Cpu {
+pub struct Cpu<B = Memory> {
 	/// Program counter
 	pub pc: u16,
 	/// Cpu registers
 	pub registers: Registers,
 
-	pub memory: Memory,
+	pub memory: B,
 
 	pub tick: u8, // T-cycle
 	pub halted: bool,
+	/// Stopped by STOP until a button is pressed
+	pub stopped: bool,
 	ime: bool,
+	/// EI was executed, IME is set after the next instruction
+	ei_delay: bool,
 }
 
 macro_rules! update_flags {
@@ -42,7 +48,7 @@ macro_rules! update_flags {
 	}
 }
 
-impl Cpu {
+impl<B: Bus> Cpu<B> {
 	fn trace(&self) {
 		let a = self.read(Register8::A);
 		let f = self.registers.flags();
@@ -69,13 +75,19 @@ impl Cpu {
 	}
 
 	/// Execute an instruction and increment pc
-	pub fn step(&mut self) -> u8 {
+	pub fn step(&mut self) -> u32 {
 		// self.trace();
 		let mut total_tick = 0;
 
 		self.tick = 0;
+		// Only an EI before this instruction enables interrupts after it
+		let enable_ime = self.ei_delay;
 
-		if self.halted {
+		if self.stopped {
+			// Only the joypad ends STOP, whatever IME says
+			self.stopped = !self.memory.joypad_input();
+			self.tick += 4;
+		} else if self.halted {
 			self.tick += 4;
 		} else if let Some(inst) = self.parse_instruction() {
 			self.pc += inst.size();
@@ -84,16 +96,31 @@ impl Cpu {
 			panic!()
 		}
 
-		total_tick += self.tick;
+		// Unless this instruction was DI
+		if enable_ime && self.ei_delay {
+			self.ime = true;
+			self.ei_delay = false;
+		}
+
+		total_tick += self.tick as u32;
 
-		self.memory.update(self.tick);
+		self.memory.tick(self.tick);
 
 		if self.ime {
 			self.tick = 0;
 			self.check_irqs();
-			self.memory.update(self.tick);
+			self.memory.tick(self.tick);
 
-			total_tick += self.tick;
+			total_tick += self.tick as u32;
+		}
+
+		// The cpu sits idle during VRAM DMA and speed switches while the
+		// rest of the system keeps going
+		let mut stall = self.memory.take_stall();
+		while stall > 0 {
+			self.memory.tick(4);
+			total_tick += 4;
+			stall = stall.saturating_sub(4) + self.memory.take_stall();
 		}
 
 		total_tick
@@ -101,23 +128,18 @@ impl Cpu {
 
 	/// Checks IRQs and execute ISRs if requested.
 	fn check_irqs(&mut self) {
-		// Bit 0 has the highest priority
-		for i in 0..5 {
-			let irq = self.memory.int_flag & (1 << i) > 0;
-			let ie = self.memory.int_enable & (1 << i) > 0;
+		let pending = self.memory.pending_interrupts();
 
-			// If interrupt is requested and enabled
-			if irq && ie {
-				self.call_isr(i);
-				break;
-			}
+		// Bit 0 has the highest priority
+		if pending != 0 {
+			self.call_isr(pending.trailing_zeros() as u8);
 		}
 	}
 
 	/// Calls requested interrupt service routine.
 	fn call_isr(&mut self, id: u8) {
 		// Reset corresponding bit in IF
-		self.memory.int_flag &= !(1 << id);
+		self.memory.acknowledge(id);
 		// Clear IME (disable any further interrupts)
 		self.ime = false;
 		self.halted = false;
@@ -126,8 +148,8 @@ impl Cpu {
 			0 => 0x40,
 			1 => 0x48,
 			2 => 0x50,
-			3 => 0x80,
-			4 => 0x70,
+			3 => 0x58,
+			4 => 0x60,
 			_ => panic!("Invalid IRQ id {}", id),
 		};
 
@@ -140,7 +162,13 @@ impl Cpu {
 		self.tick += instruction.ticks();
 		match instruction {
 			Instruction::Nop => {} // TODO: does this do anything?
-			Instruction::Stop => todo!("{:?}", instruction),
+			Instruction::Stop => {
+				if self.memory.speed_switch_armed() {
+					self.memory.switch_speed();
+				} else {
+					self.stopped = true;
+				}
+			}
 			Instruction::Halt => self.halted = true,
 			Instruction::StoreImm16(reg, val) => {
 				self.registers[reg] = val;
@@ -318,8 +346,11 @@ impl Cpu {
 				self.ime = false;
 				self._ret()
 			}
-			Instruction::Di => self.ime = false,
-			Instruction::Ei => self.ime = true,
+			Instruction::Di => {
+				self.ime = false;
+				self.ei_delay = false;
+			}
+			Instruction::Ei => self.ei_delay = true,
 			Instruction::Call(f, jump) => match f {
 				Some(flag) => {
 					if self.registers.flag(flag) {
@@ -332,8 +363,9 @@ impl Cpu {
 				self.pc = self.registers[Register16::HL];
 			}
 			Instruction::Rst(val) => {
-				self.push(upper(self.pc + 1));
-				self.push(lower(self.pc + 1));
+				// pc already points past the instruction
+				self.push(upper(self.pc));
+				self.push(lower(self.pc));
 				self.pc = val as u16;
 			}
 			Instruction::LdHlSp8(_) => todo!("{:?}", instruction),
@@ -424,7 +456,7 @@ impl Cpu {
 	}
 }
 
-impl Cpu {
+impl<B: Bus> Cpu<B> {
 	fn _add(&mut self, val: u8) {
 		let orig = self.read(Register8::A);
 		let half_carry = (orig & 0xf) + (val & 0xf) > 0xf;
@@ -657,7 +689,7 @@ mod tests {
 			}
 			}),*
 		) => {$({
-			let mut cpu = Cpu::default();
+			let mut cpu = Cpu::<FlatBus>::default();
 			$(
 				cpu.write($reg, $value);
 			)*
@@ -678,6 +710,49 @@ mod tests {
 	const C: Register8 = Register8::C;
 	const BC: Register16 = Register16::BC;
 
+	#[test]
+	fn test_flat_bus() {
+		let mut cpu = Cpu::<FlatBus>::default();
+		let program = [
+			0x31, 0xFE, 0xFF, // ld sp, $FFFE
+			0x21, 0x00, 0xC0, // ld hl, $C000
+			0x3E, 0x42, // ld a, $42
+			0x77, // ld (hl), a
+			0xFB, // ei
+			0x00, // nop
+		];
+		cpu.memory.load(0x0000, &program);
+		// Timer interrupt requested and enabled
+		cpu.memory.write(0xFF0F, 0x04);
+		cpu.memory.write(0xFFFF, 0x04);
+
+		for _ in 0..5 {
+			cpu.step();
+		}
+		// EI waits for the next instruction
+		assert_eq!(cpu.pc, 0x0A);
+		cpu.step();
+
+		assert_eq!(cpu.memory.read(0xC000), 0x42);
+		assert_eq!(cpu.pc, 0x50);
+		assert_eq!(cpu.memory.read(0xFF0F), 0x00);
+		// Return address of the handler, after the nop
+		assert_eq!(cpu.memory.read(0xFFFC), 0x0B);
+		assert!(cpu.memory.cycles > 0);
+	}
+
+	#[test]
+	fn test_decode_all() {
+		// Right up to the end of the address space
+		let nops = Cpu::try_decode_all(&[0x00; 0x10000]);
+		assert_eq!(nops.len(), 0x10000);
+		assert_eq!(nops[0xFFFF], (0xFFFF, Instruction::Nop));
+
+		// More than fits is cut off instead of wrapping around
+		let nops = Cpu::try_decode_all(&[0x00; 0x10001]);
+		assert_eq!(nops.len(), 0x10000);
+	}
+
 	#[test]
 	fn test_inc() {
 		test_instructions! [
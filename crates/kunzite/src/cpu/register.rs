@@ -2,7 +2,7 @@ use std::ops::{Index, IndexMut};
 
 use super::{
 	instruction::{Flag, Register16, Register8},
-	Cpu,
+	Bus, Cpu,
 };
 
 #[repr(C)]
@@ -143,7 +143,7 @@ impl Default for Registers {
 	}
 }
 
-impl Cpu {
+impl<B: Bus> Cpu<B> {
 	pub fn read(&self, reg: Register8) -> u8 {
 		unsafe {
 			match reg {
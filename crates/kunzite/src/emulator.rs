@@ -2,66 +2,334 @@
 
 mod draw;
 mod function;
+mod sound;
+mod vram;
 
-use self::function::Step;
-use crate::gb::Gb;
+use self::{function::Step, sound::SoundQueue, vram::VramViewer};
+use crate::{
+	cli::Args,
+	gb::Gb,
+	gbs::GbsPlayer,
+	joypad::{Button, MAX_PLAYERS},
+	link::Link,
+	palette::{self, Palette},
+	recorder::Recorder,
+	screenshot,
+	wav::WavRecorder,
+};
 use color_eyre::Report;
 use gui::prelude::*;
-use std::time::Duration;
+use std::{
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+/// Palettes in this file are loaded on startup alongside the builtin ones,
+/// more can be dropped onto the window
+const USER_PALETTES: &str = "palettes.pal";
 
 /// The emulator
 pub struct Emulator {
 	gb: Gb,
+	/// Plays a GBS file instead of running a game
+	gbs: Option<GbsPlayer>,
+	/// Second Game Boy on the link cable
+	linked: Option<Linked>,
 	run: bool,
 	screen_texture: DrawTexture,
-	ticks: u32,
+	/// Frame count of the last frame shown
+	frame: u64,
 	breakpoints: (bool, Vec<u16>),
+	palettes: Vec<Palette>,
+	palette: usize,
+	vram: VramViewer,
+	recorder: Option<Recorder>,
+	wav: Option<WavRecorder>,
+	/// The sound recording was started along with the video and stops with it
+	video_wav: bool,
+	/// Where the VGM log being recorded goes
+	vgm_path: Option<PathBuf>,
+	sound: SoundQueue,
+}
+
+/// A second Game Boy and its screen
+struct Linked {
+	link: Link,
+	screen_texture: DrawTexture,
+	/// Frame count of the last frame shown
+	frame: u64,
+}
+
+/// Keyboard layout of each joypad, in the order of the [`Button`] variants
+///
+/// Players 2-4 are only read once an SGB game enables multiplayer. With two
+/// Game Boys linked, player 2's keys control the second one.
+const KEY_MAPS: [[VirtualKeyCode; 8]; MAX_PLAYERS] = {
+	use VirtualKeyCode::*;
+
+	[
+		// Right, Left, Up, Down, A, B, Select, Start
+		[Right, Left, Up, Down, X, Z, A, S],
+		[L, J, I, K, O, U, Key7, Key8],
+		[
+			Numpad6, Numpad4, Numpad8, Numpad5, Numpad9, Numpad7, Numpad1, Numpad3,
+		],
+		[PageDown, Delete, Home, End, PageUp, Insert, Key9, Key0],
+	]
+};
+
+/// Buttons in the order used by [`KEY_MAPS`]
+const BUTTONS: [Button; 8] = [
+	Button::Right,
+	Button::Left,
+	Button::Up,
+	Button::Down,
+	Button::A,
+	Button::B,
+	Button::Select,
+	Button::Start,
+];
+
+impl Emulator {
+	/// Saves the screen to the first free `screenshot-N.png`
+	fn screenshot(&self, factor: usize) {
+		let path = free_path("screenshot", &["png"]);
+
+		let rgb = self.gb.frame_rgb(&self.palettes[self.palette]);
+		match screenshot::save_png(&path, &rgb, self.gb.frame_size(), factor) {
+			Ok(()) => println!("Saved {}", path.display()),
+			Err(err) => eprintln!("Failed to save {}: {}", path.display(), err),
+		}
+	}
+
+	/// Starts recording to the first free `recording-N.<ext>`, with `sound`
+	/// the sound goes to `recording-N.wav` alongside
+	pub fn start_recording(&mut self, ext: &str, sound: bool) {
+		let path = free_path("recording", &[ext, "wav"]);
+
+		match Recorder::create(&path, self.gb.frame_size()) {
+			Ok(recorder) => {
+				println!("Recording to {}", path.display());
+				self.recorder = Some(recorder);
+			}
+			Err(err) => {
+				eprintln!("Failed to record to {}: {}", path.display(), err);
+				return;
+			}
+		}
+
+		if sound && self.wav.is_none() {
+			self.start_wav_at(&path.with_extension("wav"), false);
+			self.video_wav = self.wav.is_some();
+		}
+	}
+
+	pub fn stop_recording(&mut self) {
+		if self.video_wav {
+			self.stop_wav();
+		}
+
+		if let Some(recorder) = self.recorder.take() {
+			let frames = recorder.frames();
+			match recorder.finish() {
+				Ok(()) => println!("Recorded {} frames", frames),
+				Err(err) => eprintln!("Failed to finish recording: {}", err),
+			}
+		}
+	}
+
+	/// Starts recording the sound to the first free `recording-N.wav`
+	pub fn start_wav(&mut self, stems: bool) {
+		let path = free_path("recording", &["wav"]);
+		self.start_wav_at(&path, stems);
+	}
+
+	fn start_wav_at(&mut self, path: &Path, stems: bool) {
+		let audio = &mut self.gb.cpu.memory.audio;
+
+		match WavRecorder::create(path, audio.sample_rate(), stems) {
+			Ok(wav) => {
+				println!("Recording sound to {}", path.display());
+				// Drop any rate control, the file is at the nominal rate
+				audio.set_sample_rate(audio.sample_rate());
+				audio.set_stems(stems);
+				self.wav = Some(wav);
+			}
+			Err(err) => eprintln!("Failed to record to {}: {}", path.display(), err),
+		}
+	}
+
+	pub fn stop_wav(&mut self) {
+		self.gb.cpu.memory.audio.set_stems(false);
+		self.video_wav = false;
+
+		if let Some(wav) = self.wav.take() {
+			let seconds = wav.seconds();
+			match wav.finish() {
+				Ok(()) => println!("Recorded {:.1}s of sound", seconds),
+				Err(err) => eprintln!("Failed to finish recording: {}", err),
+			}
+		}
+	}
+
+	/// Starts logging the sound registers, saved to the first free
+	/// `recording-N.vgm` when stopped
+	pub fn start_vgm(&mut self) {
+		let path = free_path("recording", &["vgm"]);
+		println!("Logging sound to {}", path.display());
+		self.gb.cpu.memory.audio.start_vgm();
+		self.vgm_path = Some(path);
+	}
+
+	pub fn stop_vgm(&mut self) {
+		let vgm = self.gb.cpu.memory.audio.stop_vgm();
+
+		if let (Some(path), Some(vgm)) = (self.vgm_path.take(), vgm) {
+			let seconds = vgm.seconds();
+			match vgm.save(&path) {
+				Ok(()) => println!("Logged {:.1}s of sound", seconds),
+				Err(err) => eprintln!("Failed to save {}: {}", path.display(), err),
+			}
+		}
+	}
+
+	/// Maps a key to a player and one of their joypad buttons
+	fn joypad_button(key: VirtualKeyCode) -> Option<(usize, Button)> {
+		KEY_MAPS.iter().enumerate().find_map(|(player, keys)| {
+			let idx = keys.iter().position(|k| *k == key)?;
+			Some((player, BUTTONS[idx]))
+		})
+	}
+}
+
+/// First `<prefix>-N.<ext>` in the working directory that doesn't exist yet
+/// with any of the extensions, the path has the first one
+fn free_path(prefix: &str, exts: &[&str]) -> PathBuf {
+	(0..)
+		.map(|n| PathBuf::from(format!("{}-{}", prefix, n)))
+		.find(|stem| exts.iter().all(|ext| !stem.with_extension(ext).exists()))
+		.unwrap()
+		.with_extension(exts[0])
 }
 
 impl Application for Emulator {
 	type Error = Report;
+	type Config = Args;
 
-	fn setup(system: &mut System) -> Self {
+	fn setup(system: &mut System, args: Args) -> Self {
 		let mut gb = Gb::create();
+		let device = args
+			.serial
+			.device()
+			.expect("Failed to connect the serial port.");
+		gb.cpu.memory.serial.connect(device);
 
-		let screen_texture = system.create_texture(160, 144);
+		let gbs = if args.gbs() {
+			let mut player =
+				GbsPlayer::insert(&mut gb, &args.rom).expect("Failed to load GBS file.");
+			if let Some(track) = args.track {
+				player
+					.select_track(&mut gb, track)
+					.expect("Failed to select track.");
+			}
+			Some(player)
+		} else {
+			gb.insert_rom(&args.rom).expect("Failed to load ROM.");
+			gb.boot();
+			None
+		};
 
-		gb.boot();
+		let linked = args.link.as_ref().map(|path| {
+			let link = Link::insert(&mut gb, path).expect("Failed to load linked ROM.");
+			let (width, height) = link.gb.frame_size();
+			Linked {
+				screen_texture: system.create_texture(width, height),
+				frame: 0,
+				link,
+			}
+		});
 
-		gb.insert_rom("roms/dmg-acid2.gb")
-			.expect("Failed to load ROM.");
+		let (width, height) = gb.frame_size();
+		let screen_texture = system.create_texture(width, height);
+		let vram = VramViewer::new(system);
+
+		let mut palettes = Palette::builtin();
+		if Path::new(USER_PALETTES).exists() {
+			match Palette::load(USER_PALETTES) {
+				Ok(user) => palettes.extend(user),
+				Err(err) => eprintln!("Failed to load {}: {}", USER_PALETTES, err),
+			}
+		}
 
 		Self {
+			// Music starts playing right away
+			run: gbs.is_some(),
 			gb,
-			run: false,
+			gbs,
+			linked,
 			screen_texture,
-			ticks: 0,
+			frame: 0,
 			breakpoints: (false, vec![0x8e]),
+			palettes,
+			palette: 0,
+			vram,
+			recorder: None,
+			wav: None,
+			video_wav: false,
+			vgm_path: None,
+			sound: SoundQueue::new(),
 		}
 	}
 
 	fn handle_event(&mut self, event: Event, running: &mut bool) -> Result<(), Self::Error> {
 		match event {
-			Event::Quit => *running = false,
-			Event::DroppedFile(_) => {}
+			Event::Quit => {
+				self.stop_recording();
+				self.stop_wav();
+				self.stop_vgm();
+				*running = false;
+			}
+			Event::DroppedFile(path) => {
+				if path
+					.extension()
+					.map_or(false, |ext| ext == palette::EXTENSION)
+				{
+					match Palette::load(&path) {
+						Ok(user) => self.palettes.extend(user),
+						Err(err) => eprintln!("Failed to load {}: {}", path.display(), err),
+					}
+				}
+			}
+			// `repeat` is set while the key is held down
 			Event::Keypress {
 				keycode: Some(key),
-				repeat: false,
-				..
-			} => match key {
-				VirtualKeyCode::Space => self.step(Step::InstCount(1)),
-				VirtualKeyCode::F => self.step(Step::Frame),
-				VirtualKeyCode::Return => self.run = !self.run,
-				VirtualKeyCode::D => self.breakpoints.0 = !self.breakpoints.0,
-				// VirtualKeyCode::P => {
-				// 	println!("{}", self.gb.cpu.memory.ppu.temp.iter().max().unwrap());
-				// }
-				// VirtualKeyCode::A => {
-				// 	while self.gb.cpu.memory.ppu.ly != 144 {
-				// 		self.step(Step::InstCount(1));
-				// 	}
-				// }
-				_ => {}
+				keymod,
+				repeat,
+			} => match Self::joypad_button(key) {
+				Some((player, button)) => match &mut self.linked {
+					Some(linked) if player == 1 => linked.link.gb.set_button(0, button, repeat),
+					Some(_) if player > 1 => {}
+					_ => self.gb.set_button(player, button, repeat),
+				},
+				None if !repeat => match key {
+					VirtualKeyCode::Space => self.step(Step::InstCount(1)),
+					VirtualKeyCode::F => self.step(Step::Frame),
+					VirtualKeyCode::Return => self.run = !self.run,
+					VirtualKeyCode::D => self.breakpoints.0 = !self.breakpoints.0,
+					// Shift saves at the size of the display window
+					VirtualKeyCode::F12 if keymod.shift() => self.screenshot(2),
+					VirtualKeyCode::F12 => self.screenshot(1),
+					// VirtualKeyCode::P => {
+					// 	println!("{}", self.gb.cpu.memory.ppu.temp.iter().max().unwrap());
+					// }
+					// VirtualKeyCode::A => {
+					// 	while self.gb.cpu.memory.ppu.ly != 144 {
+					// 		self.step(Step::InstCount(1));
+					// 	}
+					// }
+					_ => {}
+				},
+				None => {}
 			},
 			_ => {}
 		}
@@ -73,13 +341,18 @@ impl Application for Emulator {
 		if self.run {
 			self.step(Step::InstCount(1000));
 		}
+		self.drain_audio();
 
 		Ok(())
 	}
 
 	fn draw(&mut self, ui: &Ui) {
+		self.draw_menu(ui);
 		self.render_cpu_state(ui);
 		self.draw_memory(ui);
 		self.draw_display(ui);
+		self.draw_vram(ui);
+		self.draw_sound(ui);
+		self.draw_gbs_player(ui);
 	}
 }
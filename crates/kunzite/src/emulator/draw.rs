@@ -5,11 +5,73 @@ use crate::{
 };
 use gui::prelude::*;
 
-const SCREEN_WIDTH: f32 = 160.0;
-const SCREEN_HEIGHT: f32 = 144.0;
 const ZOOM_FACTOR: f32 = 2.0;
 
 impl Emulator {
+	pub fn draw_menu(&mut self, ui: &Ui) {
+		ui.main_menu_bar(|| {
+			ui.menu("Video", || {
+				let ppu = &mut self.gb.cpu.memory.ppu;
+				let mut options = ppu.options();
+
+				MenuItem::new("BG").build_with_ref(ui, &mut options.bg);
+				MenuItem::new("Window").build_with_ref(ui, &mut options.window);
+				MenuItem::new("Sprites").build_with_ref(ui, &mut options.sprites);
+				ui.separator();
+				MenuItem::new("10 sprites per line").build_with_ref(ui, &mut options.sprite_limit);
+
+				ppu.set_options(options);
+			});
+
+			ui.menu("Record", || {
+				if self.recorder.is_some() {
+					if MenuItem::new("Stop").build(ui) {
+						self.stop_recording();
+					}
+				} else {
+					if MenuItem::new("Start GIF").build(ui) {
+						self.start_recording("gif", false);
+					}
+					if MenuItem::new("Start Y4M").build(ui) {
+						self.start_recording("y4m", false);
+					}
+					// The sound goes to a WAV next to the video
+					if self.wav.is_none() {
+						if MenuItem::new("Start GIF with sound").build(ui) {
+							self.start_recording("gif", true);
+						}
+						if MenuItem::new("Start Y4M with sound").build(ui) {
+							self.start_recording("y4m", true);
+						}
+					}
+				}
+
+				ui.separator();
+				if self.wav.is_some() {
+					if MenuItem::new("Stop WAV").build(ui) {
+						self.stop_wav();
+					}
+				} else {
+					if MenuItem::new("Start WAV").build(ui) {
+						self.start_wav(false);
+					}
+					if MenuItem::new("Start WAV with stems").build(ui) {
+						self.start_wav(true);
+					}
+				}
+
+				ui.separator();
+				if self.vgm_path.is_some() {
+					if MenuItem::new("Stop VGM").build(ui) {
+						self.stop_vgm();
+					}
+				} else if MenuItem::new("Start VGM").build(ui) {
+					self.start_vgm();
+				}
+			});
+		});
+	}
+
 	pub fn render_cpu_state(&self, ui: &Ui) {
 		Window::new("CPU State").build(ui, || {
 			let a = self.gb.cpu.read(Register8::A);
@@ -53,6 +115,7 @@ impl Emulator {
 			ui.text(format!("Ticks: {}", self.gb.cpu.tick));
 			ui.text(format!("Running: {}", self.run));
 			ui.text(format!("Halted: {}", self.gb.cpu.halted));
+			ui.text(format!("Stopped: {}", self.gb.cpu.stopped));
 		});
 	}
 
@@ -132,13 +195,31 @@ impl Emulator {
 		});
 	}
 
-	pub fn draw_display(&self, ui: &Ui) {
+	pub fn draw_display(&mut self, ui: &Ui) {
 		Window::new("Display").resizable(false).build(ui, || {
+			let names: Vec<String> = self.palettes.iter().map(|p| p.name.clone()).collect();
+			let mut selected = self.palette;
+			if ui.combo_simple_string("Palette", &mut selected, &names) {
+				self.palette = selected;
+				self.update_screen();
+			}
+
+			let (width, height) = self.gb.frame_size();
 			Image::new(self.screen_texture.texture_id, [
-				SCREEN_WIDTH * ZOOM_FACTOR,
-				SCREEN_HEIGHT * ZOOM_FACTOR,
+				width as f32 * ZOOM_FACTOR,
+				height as f32 * ZOOM_FACTOR,
 			])
 			.build(ui);
+
+			if let Some(linked) = &self.linked {
+				let (width, height) = linked.link.gb.frame_size();
+				ui.same_line();
+				Image::new(linked.screen_texture.texture_id, [
+					width as f32 * ZOOM_FACTOR,
+					height as f32 * ZOOM_FACTOR,
+				])
+				.build(ui);
+			}
 		});
 	}
 }
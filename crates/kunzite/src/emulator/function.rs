@@ -1,6 +1,6 @@
 use super::Emulator;
-
-const FRAME_CYCLES: u32 = 456 * (144 + 10);
+use crate::{gb::Gb, palette::Palette};
+use gui::prelude::*;
 
 pub enum Step {
 	InstCount(usize),
@@ -9,11 +9,56 @@ pub enum Step {
 
 impl Emulator {
 	pub fn update_screen(&mut self) {
-		let fb = self.gb.cpu.memory.ppu.frame_buffer();
-		self.screen_texture.refresh(|x, y| {
-			let v = fb[x + (y * 160)];
-			[v, v, v]
-		});
+		let palette = &self.palettes[self.palette];
+		refresh_screen(&mut self.screen_texture, &self.gb, palette);
+
+		if let Some(linked) = &mut self.linked {
+			refresh_screen(&mut linked.screen_texture, &linked.link.gb, palette);
+		}
+	}
+
+	/// Shows the last frame of the second Game Boy, if it finished a new one
+	fn end_link_frame(&mut self) {
+		if let Some(linked) = &mut self.linked {
+			let frame = linked.link.gb.frame_count();
+			if frame != linked.frame {
+				linked.frame = frame;
+				let palette = &self.palettes[self.palette];
+				refresh_screen(&mut linked.screen_texture, &linked.link.gb, palette);
+			}
+		}
+	}
+
+	/// Called after every V-Blank with the finished frame
+	fn end_frame(&mut self) {
+		self.frame = self.gb.frame_count();
+		let palette = &self.palettes[self.palette];
+		refresh_screen(&mut self.screen_texture, &self.gb, palette);
+
+		if let Some(recorder) = &mut self.recorder {
+			let rgb = self.gb.frame_rgb(&self.palettes[self.palette]);
+			if let Err(err) = recorder.frame(&rgb) {
+				eprintln!("Recording stopped: {}", err);
+				self.recorder = None;
+			}
+		}
+	}
+
+	/// Hands the samples produced so far to the recording and the queue
+	pub fn drain_audio(&mut self) {
+		let audio = &mut self.gb.cpu.memory.audio;
+		let samples = audio.take_samples();
+
+		if let Some(wav) = &mut self.wav {
+			let stems = audio.take_stems();
+			if let Err(err) = wav.write(&samples, stems.as_deref()) {
+				eprintln!("Sound recording stopped: {}", err);
+				audio.set_stems(false);
+				self.wav = None;
+			}
+		}
+
+		self.sound.update(audio, samples, self.wav.is_some());
 	}
 
 	pub fn step(&mut self, step: Step) {
@@ -26,20 +71,39 @@ impl Emulator {
 						self.run = false;
 						break;
 					}
-					self.ticks += self.gb.step() as u32;
-					if self.ticks >= FRAME_CYCLES {
-						self.update_screen();
-						self.ticks = 0;
+					match (&mut self.gbs, &mut self.linked) {
+						(Some(player), _) => player.step(&mut self.gb),
+						(None, Some(linked)) => linked.link.step(&mut self.gb),
+						(None, None) => self.gb.step(),
+					};
+					if self.gb.frame_count() != self.frame {
+						self.end_frame();
 					}
+					self.end_link_frame();
 				}
 			}
 			Step::Frame => {
-				while self.ticks <= FRAME_CYCLES {
-					self.ticks += self.gb.step() as u32;
+				match (&mut self.gbs, &mut self.linked) {
+					(Some(player), _) => player.run_frame(&mut self.gb),
+					(None, Some(linked)) => linked.link.run_frame(&mut self.gb),
+					(None, None) => self.gb.run_frame(),
+				};
+				// No V-Blank with the LCD off, so there is no new frame
+				if self.gb.frame_count() != self.frame {
+					self.end_frame();
 				}
-				self.update_screen();
-				self.ticks = 0;
+				self.end_link_frame();
 			}
 		}
 	}
 }
+
+/// Draws the last frame of `gb` into its texture
+fn refresh_screen(texture: &mut DrawTexture, gb: &Gb, palette: &Palette) {
+	let (width, _) = gb.frame_size();
+	let rgb = gb.frame_rgb(palette);
+	texture.refresh(|x, y| {
+		let ix = (x + (y * width)) * 3;
+		[rgb[ix], rgb[ix + 1], rgb[ix + 2]]
+	});
+}
@@ -0,0 +1,158 @@
+use super::Emulator;
+use crate::audio::{Audio, CHANNELS};
+use gui::prelude::*;
+use std::{
+	collections::VecDeque,
+	time::{Duration, Instant},
+};
+
+/// Samples buffered by the frontend, 100 ms of stereo at most
+const QUEUE_SECONDS: f64 = 0.1;
+
+const CHANNEL_NAMES: [&str; CHANNELS] = ["1 - Square", "2 - Square", "3 - Wave", "4 - Noise"];
+/// Duty cycles of the square channels
+const DUTY_NAMES: [&str; 4] = ["12.5%", "25%", "50%", "75%"];
+/// Output levels of the wave channel
+const LEVEL_NAMES: [&str; 4] = ["Mute", "100%", "50%", "25%"];
+
+/// Audio waiting to be played, consumed in real time
+///
+/// `Application::update` isn't called at a steady pace, so the fill level
+/// of the queue steers the APU's dynamic rate control. Without an output
+/// device the samples are dropped as they would have been played.
+pub struct SoundQueue {
+	samples: VecDeque<f32>,
+	/// Last time samples were played
+	played: Instant,
+}
+
+impl SoundQueue {
+	pub fn new() -> Self {
+		Self {
+			samples: VecDeque::new(),
+			played: Instant::now(),
+		}
+	}
+
+	/// Queues new samples taken from `audio`, plays those that are due and
+	/// adjusts the rate to keep the queue half full
+	///
+	/// While `recording` the rate stays at the one in the WAV header, or the
+	/// recording would drift.
+	pub fn update(&mut self, audio: &mut Audio, samples: Vec<f32>, recording: bool) {
+		let rate = audio.sample_rate() as f64;
+		let capacity = (rate * QUEUE_SECONDS) as usize * 2;
+
+		self.samples.extend(samples);
+
+		let now = Instant::now();
+		let due = (now - self.played).as_secs_f64() * rate;
+		let due = (due as usize * 2).min(self.samples.len());
+		if due > 0 {
+			// Only move forward by what was played, the rest carries over
+			self.played += Duration::from_secs_f64((due / 2) as f64 / rate);
+			self.samples.drain(..due);
+		}
+
+		// Too far behind, the emulator was paused or stepped
+		if self.samples.is_empty() {
+			self.played = now;
+		}
+		if self.samples.len() > capacity {
+			let excess = self.samples.len() - capacity;
+			self.samples.drain(..excess);
+		}
+
+		// Half full is the nominal rate
+		let fill = if recording {
+			0.5
+		} else {
+			self.samples.len() as f64 / capacity as f64
+		};
+		audio.adjust_rate(fill);
+	}
+}
+
+impl Emulator {
+	/// Song selection when playing a GBS file
+	pub fn draw_gbs_player(&mut self, ui: &Ui) {
+		let player = match &mut self.gbs {
+			Some(player) => player,
+			None => return,
+		};
+		let gb = &mut self.gb;
+
+		Window::new("GBS Player").build(ui, || {
+			let header = player.header();
+			ui.text(&header.title);
+			ui.text(&header.author);
+			ui.text(&header.copyright);
+			ui.separator();
+
+			let songs = player.songs();
+			let song = player.song();
+			ui.text(format!("Track {} of {}", song + 1, songs));
+
+			let mut next = None;
+			if ui.button("Previous") && song > 0 {
+				next = Some(song - 1);
+			}
+			ui.same_line();
+			if ui.button("Restart") {
+				next = Some(song);
+			}
+			ui.same_line();
+			if ui.button("Next") && song + 1 < songs {
+				next = Some(song + 1);
+			}
+
+			if let Some(song) = next {
+				player.start_song(gb, song);
+			}
+		});
+	}
+
+	pub fn draw_sound(&mut self, ui: &Ui) {
+		let audio = &mut self.gb.cpu.memory.audio;
+
+		Window::new("Sound").build(ui, || {
+			for (channel, name) in CHANNEL_NAMES.iter().enumerate() {
+				let info = audio.channel_info(channel);
+				let state = if info.enabled { "on" } else { "off" };
+				ui.text(format!("{} ({})", name, state));
+
+				// The wave channel has an output level instead of an envelope
+				let volume = if channel == 2 {
+					format!("Level: {}", LEVEL_NAMES[info.volume as usize])
+				} else {
+					format!("Volume: {:2}", info.volume)
+				};
+				let duty = info.duty.map_or(String::new(), |duty| {
+					format!("  Duty: {}", DUTY_NAMES[duty as usize])
+				});
+				ui.text(format!(
+					"{:8.1} Hz  {}  Length: {:3}{}",
+					info.frequency, volume, info.length, duty
+				));
+
+				let scope = audio.scope(channel);
+				ui.plot_lines(&format!("##scope{}", channel), &scope)
+					.graph_size([400.0, 60.0])
+					.scale_min(-1.0)
+					.scale_max(1.0)
+					.build();
+
+				let mut muted = audio.muted(channel);
+				if ui.checkbox(&format!("Mute##{}", channel), &mut muted) {
+					audio.set_muted(channel, muted);
+				}
+				ui.same_line();
+				let mut solo = audio.solo(channel);
+				if ui.checkbox(&format!("Solo##{}", channel), &mut solo) {
+					audio.set_solo(channel, solo);
+				}
+				ui.separator();
+			}
+		});
+	}
+}
@@ -0,0 +1,265 @@
+use super::Emulator;
+use crate::ppu::{Color, PpuDebug};
+use gui::prelude::*;
+
+/// Tiles per row in the tile data viewer, per bank
+const TILES_PER_ROW: usize = 16;
+/// Sprites per row in the OAM viewer
+const SPRITES_PER_ROW: usize = 8;
+
+const VIEWPORT_COLOR: [u8; 3] = [0xff, 0x00, 0x00];
+/// Shown where a sprite pixel is transparent
+const TRANSPARENT_COLOR: [u8; 3] = [0x40, 0x40, 0x40];
+
+/// Textures and settings of the VRAM viewer windows
+pub struct VramViewer {
+	tiles: DrawTexture,
+	bg_map: DrawTexture,
+	window_map: DrawTexture,
+	oam: DrawTexture,
+	palettes: DrawTexture,
+	/// Palette used to draw the tile data, index into [`tile_palettes`]
+	tile_palette: usize,
+}
+
+impl VramViewer {
+	pub fn new(system: &mut System) -> Self {
+		let tiles_h = PpuDebug::TILES / TILES_PER_ROW * 8;
+
+		Self {
+			// Both banks side by side
+			tiles: system.create_texture(TILES_PER_ROW * 8 * 2, tiles_h),
+			bg_map: system.create_texture(256, 256),
+			window_map: system.create_texture(256, 256),
+			oam: system.create_texture(SPRITES_PER_ROW * 8, 40 / SPRITES_PER_ROW * 16),
+			// One row per palette, BG palettes first
+			palettes: system.create_texture(4, 16),
+			tile_palette: 0,
+		}
+	}
+}
+
+/// Palettes that can be used to draw the tile data, as (name, obj, palette)
+fn tile_palettes(cgb: bool) -> Vec<(String, bool, u8)> {
+	if cgb {
+		let bg = (0..8).map(|i| (format!("BG {}", i), false, i));
+		let obj = (0..8).map(|i| (format!("OBJ {}", i), true, i));
+		bg.chain(obj).collect()
+	} else {
+		vec![
+			("BGP".to_string(), false, 0),
+			("OBP0".to_string(), true, 0),
+			("OBP1".to_string(), true, 1),
+		]
+	}
+}
+
+/// Whether a map pixel lies on the edge of a `w`x`h` rectangle starting at
+/// (`left`, `top`), wrapping around the 256x256 map
+fn on_rect_edge(x: usize, y: usize, left: usize, top: usize, w: usize, h: usize) -> bool {
+	let dx = x.wrapping_sub(left) & 0xff;
+	let dy = y.wrapping_sub(top) & 0xff;
+
+	dx < w && dy < h && (dx == 0 || dx == w - 1 || dy == 0 || dy == h - 1)
+}
+
+impl Emulator {
+	pub fn draw_vram(&mut self, ui: &Ui) {
+		self.draw_tiles(ui);
+		self.draw_tile_maps(ui);
+		self.draw_oam(ui);
+		self.draw_palettes(ui);
+	}
+
+	fn draw_tiles(&mut self, ui: &Ui) {
+		let ppu = self.gb.cpu.memory.ppu.debug();
+		let dmg = &self.palettes[self.palette];
+		let viewer = &mut self.vram;
+
+		Window::new("Tiles").build(ui, || {
+			let palettes = tile_palettes(ppu.cgb());
+			let names: Vec<&String> = palettes.iter().map(|p| &p.0).collect();
+			viewer.tile_palette = viewer.tile_palette.min(palettes.len() - 1);
+			ui.combo_simple_string("Palette", &mut viewer.tile_palette, &names);
+
+			let (_, obj, palette) = palettes[viewer.tile_palette];
+			viewer.tiles.refresh(|x, y| {
+				let bank = x / (TILES_PER_ROW * 8);
+				let tile = (y / 8) * TILES_PER_ROW + (x / 8) % TILES_PER_ROW;
+
+				// Bank 1 only exists on CGB
+				if bank == 1 && !ppu.cgb() {
+					return [0; 3];
+				}
+
+				let color_no = ppu.tile_pixel(bank as u8, tile, x % 8, y % 8);
+				ppu.color(obj, palette, color_no).rgb(dmg)
+			});
+
+			let width = TILES_PER_ROW * 8 * 2;
+			let height = PpuDebug::TILES / TILES_PER_ROW * 8;
+			Image::new(viewer.tiles.texture_id, [
+				width as f32 * 2.0,
+				height as f32 * 2.0,
+			])
+			.build(ui);
+		});
+	}
+
+	fn draw_tile_maps(&mut self, ui: &Ui) {
+		let ppu = self.gb.cpu.memory.ppu.debug();
+		let dmg = &self.palettes[self.palette];
+		let viewer = &mut self.vram;
+
+		Window::new("BG Map").build(ui, || {
+			let (scx, scy) = ppu.scroll();
+			ui.text(format!("SCX: {:02X}  SCY: {:02X}", scx, scy));
+
+			viewer.bg_map.refresh(|x, y| {
+				if on_rect_edge(x, y, scx as usize, scy as usize, 160, 144) {
+					return VIEWPORT_COLOR;
+				}
+
+				let (color_no, attr) = ppu.map_pixel(false, x, y);
+				ppu.color(false, attr & 0x7, color_no).rgb(dmg)
+			});
+			Image::new(viewer.bg_map.texture_id, [256.0, 256.0]).build(ui);
+		});
+
+		Window::new("Window Map").build(ui, || {
+			let (wx, wy) = ppu.window();
+			let enabled = ppu.lcdc() & 0x20 > 0;
+			ui.text(format!(
+				"WX: {:02X}  WY: {:02X}  Enabled: {}",
+				wx, wy, enabled
+			));
+
+			// Part of the map that ends up on screen, the window never wraps
+			let w = 167usize.saturating_sub(wx as usize).min(160);
+			let h = 144usize.saturating_sub(wy as usize);
+			let shown = enabled && w > 0 && h > 0;
+
+			viewer.window_map.refresh(|x, y| {
+				if shown && on_rect_edge(x, y, 0, 0, w, h) {
+					return VIEWPORT_COLOR;
+				}
+
+				let (color_no, attr) = ppu.map_pixel(true, x, y);
+				ppu.color(false, attr & 0x7, color_no).rgb(dmg)
+			});
+			Image::new(viewer.window_map.texture_id, [256.0, 256.0]).build(ui);
+		});
+	}
+
+	fn draw_oam(&mut self, ui: &Ui) {
+		let ppu = self.gb.cpu.memory.ppu.debug();
+		let dmg = &self.palettes[self.palette];
+		let viewer = &mut self.vram;
+
+		Window::new("OAM").build(ui, || {
+			let height = ppu.sprite_height();
+
+			viewer.oam.refresh(|x, y| {
+				let sprite = ppu.sprite((y / 16) * SPRITES_PER_ROW + x / 8);
+				if y % 16 >= height {
+					return [0; 3];
+				}
+
+				// 8x16 sprites ignore bit 0 of the tile number
+				let tile = if height == 16 {
+					(sprite.tile & 0xfe) as usize + (y % 16) / 8
+				} else {
+					sprite.tile as usize
+				};
+				let bank = if ppu.cgb() { sprite.bank() } else { 0 };
+
+				let color_no = ppu.tile_pixel(bank, tile, x % 8, y % 8);
+				if color_no == 0 {
+					return TRANSPARENT_COLOR;
+				}
+
+				let palette = if ppu.cgb() {
+					sprite.cgb_palette()
+				} else {
+					sprite.dmg_palette()
+				};
+				ppu.color(true, palette, color_no).rgb(dmg)
+			});
+
+			Image::new(viewer.oam.texture_id, [
+				(SPRITES_PER_ROW * 8) as f32 * 4.0,
+				(40 / SPRITES_PER_ROW * 16) as f32 * 4.0,
+			])
+			.build(ui);
+
+			ui.separator();
+			ChildWindow::new("oam list").build(ui, || {
+				for i in 0..40 {
+					let sprite = ppu.sprite(i);
+					let palette = if ppu.cgb() {
+						format!("OBJ {} Bank {}", sprite.cgb_palette(), sprite.bank())
+					} else {
+						format!("OBP{}", sprite.dmg_palette())
+					};
+
+					ui.text(format!(
+						"{:02}: X {:02X} Y {:02X} Tile {:02X} {}{}{}{}",
+						i,
+						sprite.x,
+						sprite.y,
+						sprite.tile,
+						palette,
+						if sprite.flip_x() { " FlipX" } else { "" },
+						if sprite.flip_y() { " FlipY" } else { "" },
+						if sprite.behind_bg() { " BehindBG" } else { "" },
+					));
+				}
+			});
+		});
+	}
+
+	fn draw_palettes(&mut self, ui: &Ui) {
+		let ppu = self.gb.cpu.memory.ppu.debug();
+		let dmg = &self.palettes[self.palette];
+		let viewer = &mut self.vram;
+
+		Window::new("Palettes").build(ui, || {
+			// DMG only has BGP, OBP0 and OBP1
+			let used = |obj: bool, palette: u8| ppu.cgb() || palette == 0 || (obj && palette == 1);
+
+			viewer.palettes.refresh(|x, y| {
+				let (obj, palette) = (y >= 8, (y % 8) as u8);
+				if !used(obj, palette) {
+					return [0; 3];
+				}
+
+				ppu.color(obj, palette, x as u8).rgb(dmg)
+			});
+			Image::new(viewer.palettes.texture_id, [4.0 * 16.0, 16.0 * 16.0]).build(ui);
+
+			ui.same_line();
+			ui.group(|| {
+				for row in 0..16 {
+					let (obj, palette) = (row >= 8, (row % 8) as u8);
+					let name = match (ppu.cgb(), obj) {
+						(true, false) => format!("BG {}", palette),
+						(true, true) => format!("OBJ {}", palette),
+						(false, false) => "BGP".to_string(),
+						(false, true) => format!("OBP{}", palette),
+					};
+
+					let colors: Vec<String> = (0..4)
+						.map(|color_no| match ppu.color(obj, palette, color_no) {
+							Color::Shade(shade) => format!("{}", shade),
+							Color::Rgb555(color) => format!("{:04X}", color),
+						})
+						.collect();
+
+					if used(obj, palette) {
+						ui.text(format!("{:<6} {}", name, colors.join(" ")));
+					}
+				}
+			});
+		});
+	}
+}
@@ -2,9 +2,28 @@
 
 use std::path::Path;
 
-use crate::cpu::{instruction::Register16, Cpu};
+use crate::{
+	cpu::{instruction::Register16, Cpu},
+	joypad::Button,
+	palette::{rgb555, Palette},
+	sgb::{SGB_H, SGB_W},
+};
 use color_eyre::Result;
 
+/// Clock cycles in a frame, 154 lines of 456 cycles
+pub const FRAME_CYCLES: u32 = 456 * 154;
+
+/// The hardware being emulated
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Model {
+	/// Original Game Boy
+	Dmg,
+	/// Game Boy Color
+	Cgb,
+	/// Super Game Boy
+	Sgb,
+}
+
 /// Brings all the components into a single package
 pub struct Gb {
 	/// the cpu
@@ -20,15 +39,44 @@ impl Gb {
 	}
 
 	/// Insert a rom into the gameboy
+	///
+	/// The model is picked from the cartridge header, so this should happen
+	/// before [`Gb::boot`].
 	pub fn insert_rom<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-		self.cpu.memory.cartridge.insert_rom(path)
+		self.cpu.memory.insert_rom(path)?;
+
+		let model = self.cpu.memory.cartridge().model();
+		self.cpu.memory.set_model(model);
+
+		Ok(())
+	}
+
+	pub fn model(&self) -> Model {
+		self.cpu.memory.model()
 	}
 
 	pub fn boot(&mut self) {
-		self.cpu.registers[Register16::AF] = 0x0100;
-		self.cpu.registers[Register16::BC] = 0xFF13;
-		self.cpu.registers[Register16::DE] = 0x00C1;
-		self.cpu.registers[Register16::HL] = 0x8403;
+		match self.model() {
+			Model::Dmg => {
+				self.cpu.registers[Register16::AF] = 0x0100;
+				self.cpu.registers[Register16::BC] = 0xFF13;
+				self.cpu.registers[Register16::DE] = 0x00C1;
+				self.cpu.registers[Register16::HL] = 0x8403;
+			}
+			Model::Sgb => {
+				self.cpu.registers[Register16::AF] = 0x0100;
+				self.cpu.registers[Register16::BC] = 0x0014;
+				self.cpu.registers[Register16::DE] = 0x0000;
+				self.cpu.registers[Register16::HL] = 0xC060;
+			}
+			Model::Cgb => {
+				// A = 0x11 is how games detect they're running on a CGB
+				self.cpu.registers[Register16::AF] = 0x1180;
+				self.cpu.registers[Register16::BC] = 0x0000;
+				self.cpu.registers[Register16::DE] = 0xFF56;
+				self.cpu.registers[Register16::HL] = 0x000D;
+			}
+		}
 		self.cpu.registers[Register16::SP] = 0xFFFE;
 		self.cpu.pc = 0x100;
 
@@ -73,24 +121,112 @@ impl Gb {
 		self.cpu.memory.write(0xFF49, 0xFF);
 		self.cpu.memory.write(0xFF4A, 0x00);
 		self.cpu.memory.write(0xFF4B, 0x00);
-		self.cpu.memory.write(0xFF4D, 0xFF);
-		self.cpu.memory.write(0xFF4F, 0xFF);
-		self.cpu.memory.write(0xFF51, 0xFF);
-		self.cpu.memory.write(0xFF52, 0xFF);
-		self.cpu.memory.write(0xFF53, 0xFF);
-		self.cpu.memory.write(0xFF54, 0xFF);
-		self.cpu.memory.write(0xFF55, 0xFF);
-		self.cpu.memory.write(0xFF56, 0xFF);
-		self.cpu.memory.write(0xFF68, 0xFF);
-		self.cpu.memory.write(0xFF69, 0xFF);
-		self.cpu.memory.write(0xFF6A, 0xFF);
-		self.cpu.memory.write(0xFF6B, 0xFF);
-		self.cpu.memory.write(0xFF70, 0xFF);
+		self.cpu.memory.write(0xFF56, 0x3E);
 		self.cpu.memory.write(0xFFFF, 0x00);
 	}
 
+	/// Size of the picture returned by [`Gb::frame_rgb`]
+	pub fn frame_size(&self) -> (usize, usize) {
+		match self.model() {
+			Model::Sgb => (SGB_W, SGB_H),
+			Model::Dmg | Model::Cgb => (160, 144),
+		}
+	}
+
+	/// Colourises the last frame as packed RGB
+	///
+	/// `palette` is only used for DMG output, the SGB picture includes the
+	/// border.
+	pub fn frame_rgb(&self, palette: &Palette) -> Vec<u8> {
+		let memory = &self.cpu.memory;
+		let ppu = &memory.ppu;
+
+		if let Some(sgb) = &memory.sgb {
+			sgb.render(ppu.frame_buffer())
+		} else if ppu.cgb() {
+			ppu.color_buffer()
+				.iter()
+				.flat_map(|&color| rgb555(color))
+				.collect()
+		} else {
+			palette.to_rgb(ppu.frame_buffer())
+		}
+	}
+
+	/// Presses or releases a button on one of the joypads
+	///
+	/// Only player 1 is read unless an SGB game enables multiplayer.
+	pub fn set_button(&mut self, player: usize, button: Button, pressed: bool) {
+		self.cpu.memory.joypad.set_button(player, button, pressed);
+	}
+
 	/// fully execute the next instruction
-	pub fn step(&mut self) -> u8 {
+	pub fn step(&mut self) -> u32 {
 		self.cpu.step()
 	}
+
+	/// Time taken by `cycles` cpu T-cycles in normal speed T-cycles, they
+	/// pass twice as fast in double speed mode
+	pub fn elapsed(&self, cycles: u32) -> i64 {
+		if self.cpu.memory.double_speed() {
+			cycles as i64 / 2
+		} else {
+			cycles as i64
+		}
+	}
+
+	/// Number of frames completed since power on
+	pub fn frame_count(&self) -> u64 {
+		self.cpu.memory.ppu.frame_count()
+	}
+
+	/// Runs until the PPU enters V-Blank, returns the elapsed cycles
+	///
+	/// With the LCD off no V-Blank happens, so this gives up after the
+	/// length of a frame.
+	pub fn run_frame(&mut self) -> u32 {
+		let frame = self.cpu.memory.ppu.frame_count();
+		let mut cycles = 0;
+
+		while self.cpu.memory.ppu.frame_count() == frame && cycles < FRAME_CYCLES {
+			cycles += self.step();
+		}
+
+		cycles
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cpu::instruction::Register8;
+
+	#[test]
+	fn test_stop() {
+		let mut rom = vec![0; 0x8000];
+		rom[0x100..0x109].copy_from_slice(&[
+			0xAF, // xor a
+			0xE0, 0x00, // ldh (P1), a
+			0x10, 0x00, // stop
+			0x3C, // inc a
+			0xC3, 0x05, 0x01, // jp $0105
+		]);
+
+		let mut gb = Gb::create();
+		gb.cpu.memory.insert_raw(rom, 0).unwrap();
+		gb.boot();
+		for _ in 0..1000 {
+			gb.step();
+		}
+		// Interrupts are disabled, but still only the joypad wakes it up
+		assert!(gb.cpu.stopped);
+		assert_eq!(gb.cpu.pc, 0x0105);
+
+		gb.set_button(0, Button::Start, true);
+		for _ in 0..4 {
+			gb.step();
+		}
+		assert!(!gb.cpu.stopped);
+		assert_ne!(gb.cpu.read(Register8::A), 0);
+	}
 }
@@ -0,0 +1,393 @@
+//! Game Boy Sound System music files
+//!
+//! A GBS file holds the sound driver and music data ripped from a game. The
+//! data is mapped into a synthetic cartridge at its load address and the
+//! player calls the INIT routine once per song, then PLAY at the rate TMA and
+//! TAC ask for: every V-Blank, or every timer overflow when TAC enables the
+//! timer. They start out with the values from the header, drivers that
+//! reprogram the timer change the rate from the next call on.
+//!
+//! TAC bit 7 asks for CGB double speed. The player then runs the file on a
+//! CGB switched to double speed, so the driver gets twice the cpu time while
+//! the rate of PLAY stays the same in real time.
+//!
+//! The routines are called directly instead of through interrupts. Between
+//! calls the cpu spins in an idle loop, so the APU keeps running in time.
+//! Drivers ripped out of games leave IE and the vectors in whatever state
+//! suits them, and don't always return with interrupts enabled, so waiting
+//! for an interrupt could miss calls or nest them. Calling from the idle loop
+//! only ever starts PLAY after the previous call returned, and its rate
+//! doesn't depend on what the driver does to the interrupt registers.
+
+use crate::{
+	cpu::instruction::Register16,
+	gb::{Gb, Model, FRAME_CYCLES},
+};
+use color_eyre::{eyre::eyre, Result};
+use std::{fs, path::Path};
+
+/// Size of the header in front of the data
+const HEADER_SIZE: usize = 0x70;
+/// Where the idle loop lives, routines return here
+const IDLE_ADDR: u16 = 0x0100;
+/// Cartridge RAM the drivers may use
+const RAM_SIZE: usize = 8 * 1024;
+/// Largest cartridge the data can be mapped into
+const MAX_ROM_SIZE: usize = 2 * 1024 * 1024;
+/// Clocks per TIMA increment for each TAC clock select
+const TIMER_RATES: [u32; 4] = [1024, 16, 64, 256];
+
+/// Parsed GBS header
+#[derive(Debug, Clone, PartialEq)]
+pub struct GbsHeader {
+	pub version: u8,
+	/// Number of songs
+	pub songs: u8,
+	/// Song to start with, 1 based
+	pub first_song: u8,
+	/// Address the data is mapped to, at least 0x400
+	pub load: u16,
+	pub init: u16,
+	pub play: u16,
+	/// Initial stack pointer
+	pub sp: u16,
+	/// Timer modulo and control, only used if TAC bit 2 is set
+	pub tma: u8,
+	pub tac: u8,
+	pub title: String,
+	pub author: String,
+	pub copyright: String,
+}
+
+/// A loaded GBS file
+pub struct Gbs {
+	pub header: GbsHeader,
+	data: Vec<u8>,
+}
+
+impl Gbs {
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+		Self::parse(fs::read(path)?)
+	}
+
+	pub fn parse(bytes: Vec<u8>) -> Result<Self> {
+		if bytes.len() < HEADER_SIZE || &bytes[0..3] != b"GBS" {
+			return Err(eyre!("Not a GBS file"));
+		}
+
+		let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+		let text = |offset: usize| {
+			let text = &bytes[offset..offset + 0x20];
+			let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+			String::from_utf8_lossy(&text[..end]).into_owned()
+		};
+
+		let header = GbsHeader {
+			version: bytes[0x03],
+			songs: bytes[0x04],
+			first_song: bytes[0x05],
+			load: word(0x06),
+			init: word(0x08),
+			play: word(0x0A),
+			sp: word(0x0C),
+			tma: bytes[0x0E],
+			tac: bytes[0x0F],
+			title: text(0x10),
+			author: text(0x30),
+			copyright: text(0x50),
+		};
+
+		if header.version != 1 {
+			return Err(eyre!("Unsupported GBS version {}", header.version));
+		}
+		if header.songs == 0 {
+			return Err(eyre!("GBS file has no songs"));
+		}
+		if header.load < 0x400 || header.load >= 0x8000 {
+			return Err(eyre!("Invalid GBS load address 0x{:04X}", header.load));
+		}
+		if header.load as usize + bytes.len() - HEADER_SIZE > MAX_ROM_SIZE {
+			return Err(eyre!("GBS data doesn't fit into 2 MiB"));
+		}
+
+		Ok(Self {
+			header,
+			data: bytes[HEADER_SIZE..].to_vec(),
+		})
+	}
+
+	/// Builds the cartridge the data is mapped into
+	///
+	/// The RST vectors jump to the same offset from the load address, the
+	/// interrupt vectors just return and 0x100 is the idle loop.
+	fn rom(&self) -> Vec<u8> {
+		let load = self.header.load as usize;
+		let len = (load + self.data.len()).max(0x8000);
+		let mut rom = vec![0; len.next_power_of_two()];
+
+		for rst in (0x00..0x40).step_by(8) {
+			let [lo, hi] = ((load + rst) as u16).to_le_bytes();
+			rom[rst..rst + 3].copy_from_slice(&[0xC3, lo, hi]); // jp
+		}
+		for vector in (0x40..0x68).step_by(8) {
+			rom[vector] = 0xD9; // reti
+		}
+		let idle = IDLE_ADDR as usize;
+		rom[idle..idle + 2].copy_from_slice(&[0x18, 0xFE]); // jr -2
+
+		rom[load..load + self.data.len()].copy_from_slice(&self.data);
+
+		rom
+	}
+}
+
+/// Normal speed clocks between calls to PLAY for the given timer modulo and
+/// control
+pub fn play_period(tma: u8, tac: u8) -> u32 {
+	if tac & 0x04 == 0 {
+		return FRAME_CYCLES;
+	}
+
+	let period = (256 - tma as u32) * TIMER_RATES[(tac & 0x3) as usize];
+
+	// Bit 7 asks for the CGB double speed timer
+	if tac & 0x80 > 0 {
+		period / 2
+	} else {
+		period
+	}
+}
+
+/// Drives the routines of a GBS file on a [`Gb`]
+pub struct GbsPlayer {
+	gbs: Gbs,
+	/// Current song, 0 based
+	song: u8,
+	/// Normal speed clocks until PLAY is due, negative while it's overdue
+	timer: i64,
+}
+
+impl GbsPlayer {
+	/// Maps the GBS file into `gb` and starts its first song
+	pub fn insert<P: AsRef<Path>>(gb: &mut Gb, path: P) -> Result<Self> {
+		let gbs = Gbs::load(path)?;
+		gb.cpu.memory.insert_raw(gbs.rom(), RAM_SIZE)?;
+		// Only the CGB has a double speed mode
+		let model = if gbs.header.tac & 0x80 > 0 {
+			Model::Cgb
+		} else {
+			Model::Dmg
+		};
+		gb.cpu.memory.set_model(model);
+
+		let mut player = Self {
+			song: 0,
+			timer: 0,
+			gbs,
+		};
+		let first = player.gbs.header.first_song.max(1) - 1;
+		player.start_song(gb, first.min(player.songs() - 1));
+
+		Ok(player)
+	}
+
+	pub fn header(&self) -> &GbsHeader {
+		&self.gbs.header
+	}
+
+	pub fn songs(&self) -> u8 {
+		self.gbs.header.songs
+	}
+
+	/// Current song, 0 based
+	pub fn song(&self) -> u8 {
+		self.song
+	}
+
+	/// Starts a song given on the command line, 1 based
+	pub fn select_track(&mut self, gb: &mut Gb, track: u8) -> Result<()> {
+		if track == 0 || track > self.songs() {
+			return Err(eyre!("Track {} out of range 1-{}", track, self.songs()));
+		}
+		self.start_song(gb, track - 1);
+
+		Ok(())
+	}
+
+	/// Resets the machine and calls INIT for a song, 0 based
+	pub fn start_song(&mut self, gb: &mut Gb, song: u8) {
+		self.song = song;
+
+		gb.boot();
+		let memory = &mut gb.cpu.memory;
+		// Without the wait for the clock to settle that STOP would have
+		let double_speed = self.gbs.header.tac & 0x80 > 0;
+		if memory.double_speed() != double_speed {
+			memory.switch_speed();
+			memory.take_stall();
+		}
+		// Silence whatever was playing, the sound starts out on
+		memory.write(0xFF26, 0x00);
+		memory.write(0xFF26, 0x80);
+		memory.write(0xFF25, 0xFF);
+		memory.write(0xFF24, 0x77);
+		memory.write(0xFF06, self.gbs.header.tma);
+		memory.write(0xFF07, self.gbs.header.tac);
+		for addr in 0xA000..0xE000 {
+			memory.write(addr, 0);
+		}
+		for addr in 0xFF80..0xFFFF {
+			memory.write(addr, 0);
+		}
+
+		gb.cpu.registers[Register16::SP] = self.gbs.header.sp;
+		gb.cpu.registers[Register16::AF] = (song as u16) << 8;
+		self.call(gb, self.gbs.header.init);
+
+		// PLAY waits for INIT to return
+		self.timer = 0;
+	}
+
+	/// Calls a routine, which returns to the idle loop
+	fn call(&self, gb: &mut Gb, addr: u16) {
+		let [lo, hi] = IDLE_ADDR.to_le_bytes();
+		let sp = gb.cpu.registers[Register16::SP].wrapping_sub(2);
+		gb.cpu.memory.write(sp, lo);
+		gb.cpu.memory.write(sp.wrapping_add(1), hi);
+		gb.cpu.registers[Register16::SP] = sp;
+
+		gb.cpu.halted = false;
+		gb.cpu.pc = addr;
+	}
+
+	/// Runs one instruction, calling PLAY when it is due
+	pub fn step(&mut self, gb: &mut Gb) -> u32 {
+		if self.timer <= 0 && gb.cpu.pc == IDLE_ADDR {
+			// The double speed bit only exists in the header
+			let memory = &gb.cpu.memory;
+			let tac = memory.read(0xFF07) & 0x07 | self.gbs.header.tac & 0x80;
+			self.timer += play_period(memory.read(0xFF06), tac) as i64;
+			self.call(gb, self.gbs.header.play);
+		}
+
+		let cycles = gb.step();
+		self.timer -= gb.elapsed(cycles);
+
+		cycles
+	}
+
+	/// Runs for the length of a frame, returns the elapsed cycles
+	///
+	/// The frame is as long in double speed, where more cycles fit into it.
+	pub fn run_frame(&mut self, gb: &mut Gb) -> u32 {
+		let mut time = 0;
+		let mut cycles = 0;
+		while time < FRAME_CYCLES as i64 {
+			let step = self.step(gb);
+			time += gb.elapsed(step);
+			cycles += step;
+		}
+
+		cycles
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn header(tma: u8, tac: u8) -> Vec<u8> {
+		let mut bytes = vec![0; HEADER_SIZE];
+		bytes[0..4].copy_from_slice(b"GBS\x01");
+		bytes[0x04] = 3;
+		bytes[0x05] = 1;
+		bytes[0x06..0x08].copy_from_slice(&0x0400u16.to_le_bytes());
+		bytes[0x0E] = tma;
+		bytes[0x0F] = tac;
+		bytes[0x10..0x15].copy_from_slice(b"Title");
+		bytes
+	}
+
+	#[test]
+	fn test_parse() {
+		let gbs = Gbs::parse(header(0, 0)).unwrap();
+		assert_eq!(gbs.header.songs, 3);
+		assert_eq!(gbs.header.title, "Title");
+		assert_eq!(play_period(0, 0), FRAME_CYCLES);
+		// 4096 Hz timer overflowing every 64 ticks
+		assert_eq!(play_period(0xC0, 0x04), 64 * 1024);
+
+		assert!(Gbs::parse(b"GBZ".to_vec()).is_err());
+
+		// Loaded at 0x400, one byte too many for the largest cartridge
+		let mut bytes = header(0, 0);
+		bytes.resize(HEADER_SIZE + MAX_ROM_SIZE - 0x400 + 1, 0);
+		assert!(Gbs::parse(bytes).is_err());
+	}
+
+	/// Plays 10 frames of a GBS file with the given TAC and INIT, PLAY counts
+	/// its calls at 0xC000
+	fn play(name: &str, tac: u8, init: &[u8]) -> Gb {
+		let play = 0x0400 + init.len() as u16;
+		let mut bytes = header(0, tac);
+		bytes[0x08..0x0A].copy_from_slice(&0x0400u16.to_le_bytes());
+		bytes[0x0A..0x0C].copy_from_slice(&play.to_le_bytes());
+		bytes[0x0C..0x0E].copy_from_slice(&0xFFFEu16.to_le_bytes());
+		bytes.extend_from_slice(init);
+		// PLAY: ld hl, $C000; inc (hl); ret
+		bytes.extend_from_slice(&[0x21, 0x00, 0xC0, 0x34, 0xC9]);
+
+		let path = std::env::temp_dir().join(name);
+		fs::write(&path, bytes).unwrap();
+		let mut gb = Gb::create();
+		let mut player = GbsPlayer::insert(&mut gb, &path).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		for _ in 0..10 {
+			player.run_frame(&mut gb);
+		}
+		gb
+	}
+
+	#[test]
+	fn test_play_rate() {
+		// INIT: ret
+		let gb = play("kunzite-test.gbs", 0x00, &[0xC9]);
+		// Right after INIT, then once per frame
+		assert_eq!(gb.cpu.memory.read(0xC000), 11);
+		assert!(!gb.cpu.memory.double_speed());
+	}
+
+	#[test]
+	fn test_play_timer() {
+		// INIT: ld a, $00; ldh ($06), a; ld a, $06; ldh ($07), a; ret
+		let init = [0x3E, 0x00, 0xE0, 0x06, 0x3E, 0x06, 0xE0, 0x07, 0xC9];
+		let gb = play("kunzite-test-timer.gbs", 0x00, &init);
+		// The timer INIT set up overflows every 256 * 64 clocks
+		let expected = 10 * FRAME_CYCLES / play_period(0x00, 0x06) + 1;
+		assert_eq!(gb.cpu.memory.read(0xC000) as u32, expected);
+	}
+
+	#[test]
+	fn test_play_double_speed() {
+		// INIT: ld a, $00; ldh ($06), a; ld a, $06; ldh ($07), a; ret
+		let init = [0x3E, 0x00, 0xE0, 0x06, 0x3E, 0x06, 0xE0, 0x07, 0xC9];
+		let gb = play("kunzite-test-double.gbs", 0x80, &init);
+		assert_eq!(gb.model(), Model::Cgb);
+		assert!(gb.cpu.memory.double_speed());
+		// Twice as often, the timer runs twice as fast
+		let expected = 10 * FRAME_CYCLES / play_period(0x00, 0x86) + 1;
+		assert_eq!(gb.cpu.memory.read(0xC000) as u32, expected);
+	}
+
+	#[test]
+	fn test_rom() {
+		let mut bytes = header(0, 0);
+		bytes.extend_from_slice(&[0xAA, 0xBB]);
+		let rom = Gbs::parse(bytes).unwrap().rom();
+
+		assert_eq!(rom.len(), 0x8000);
+		assert_eq!(&rom[0x38..0x3B], &[0xC3, 0x38, 0x04]);
+		assert_eq!(&rom[0x400..0x402], &[0xAA, 0xBB]);
+	}
+}
@@ -0,0 +1,209 @@
+//! Golden image regression tests for the PPU
+//!
+//! Runs visual test ROMs until they signal completion with `LD B,B` and
+//! compares the picture with a reference PNG from `tests/golden`.
+//!
+//! ROMs are looked up in `roms/` first, the ones that are not part of the
+//! repository come from the directory in `KUNZITE_TEST_ROMS`. Cases whose
+//! ROM can't be found are skipped, a case that runs without a reference
+//! fails. With `KUNZITE_BLESS=1` the current output is saved as the new
+//! reference instead.
+//!
+//! Cases in `EXPECTED_FAIL` are known to render wrong. They still run and
+//! their result is reported, but doesn't fail the test, unless they start
+//! passing. They are never blessed, their reference has to come from
+//! upstream.
+//!
+//! On failure the actual picture and a diff, where mismatching pixels are
+//! red, end up in `target/golden`.
+
+use crate::{
+	gb::{Gb, FRAME_CYCLES},
+	palette::Palette,
+	ppu::RenderOptions,
+	screenshot,
+};
+use color_eyre::{eyre::eyre, Result};
+use std::{
+	env,
+	fs::{self, File},
+	panic::{self, AssertUnwindSafe},
+	path::{Path, PathBuf},
+};
+
+/// `LD B,B`, used by test ROMs as a breakpoint when they are done
+const LD_B_B: u8 = 0x40;
+/// Give up on a ROM that never finishes after this many frames
+const MAX_FRAMES: u64 = 600;
+
+/// Test ROMs, each compared with the reference of the same name
+const ROMS: &[&str] = &[
+	"dmg-acid2.gb",
+	// Runs as a CGB, checks banking, colour palettes and BG/OBJ priority
+	"cgb-acid2.gbc",
+	"m2_win_en_toggle.gb",
+	"m3_bgp_change.gb",
+	"m3_bgp_change_sprites.gb",
+	"m3_lcdc_bg_en_change.gb",
+	"m3_lcdc_bg_map_change.gb",
+	"m3_lcdc_obj_en_change.gb",
+	"m3_lcdc_obj_en_change_variant.gb",
+	"m3_lcdc_obj_size_change.gb",
+	"m3_lcdc_obj_size_change_scx.gb",
+	"m3_lcdc_tile_sel_change.gb",
+	"m3_lcdc_tile_sel_win_change.gb",
+	"m3_lcdc_win_en_change_multiple.gb",
+	"m3_lcdc_win_en_change_multiple_wx.gb",
+	"m3_lcdc_win_map_change.gb",
+	"m3_obp0_change.gb",
+	"m3_scx_high_5_bits.gb",
+	"m3_scx_low_3_bits.gb",
+	"m3_scy_change.gb",
+	"m3_window_timing.gb",
+	"m3_window_timing_wx_0.gb",
+	"m3_wx_4_change.gb",
+	"m3_wx_4_change_sprites.gb",
+	"m3_wx_5_change.gb",
+	"m3_wx_6_change.gb",
+];
+
+/// Cases known to fail, see the README next to the references
+const EXPECTED_FAIL: &[&str] = &["dmg-acid2.gb"];
+
+fn golden_dir() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Where to find a test ROM, `None` if it's nowhere to be found
+fn find_rom(rom: &str) -> Option<PathBuf> {
+	let bundled = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../roms");
+	let dirs = Some(bundled)
+		.into_iter()
+		.chain(env::var_os("KUNZITE_TEST_ROMS").map(PathBuf::from));
+
+	dirs.map(|dir| dir.join(rom)).find(|path| path.exists())
+}
+
+fn output_dir() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("../../target/golden")
+}
+
+/// Runs a ROM until `LD B,B` and returns its picture as packed RGB
+fn run(rom: &Path) -> Result<(Vec<u8>, (usize, usize))> {
+	let mut gb = Gb::create();
+	gb.insert_rom(rom)?;
+	gb.boot();
+
+	// Enhancements would make every comparison fail
+	assert_eq!(gb.cpu.memory.ppu.options(), RenderOptions::default());
+
+	let mut cycles = 0;
+	while gb.cpu.memory.read(gb.cpu.pc) != LD_B_B {
+		if cycles > MAX_FRAMES * FRAME_CYCLES as u64 {
+			return Err(eyre!("no LD B,B after {} frames", MAX_FRAMES));
+		}
+		cycles += gb.step() as u64;
+	}
+
+	// Let the frame that was being drawn finish
+	gb.run_frame();
+
+	// Grayscale matches the shades used by the reference images
+	let palette = &Palette::builtin()[0];
+	Ok((gb.frame_rgb(palette), gb.frame_size()))
+}
+
+/// Loads a PNG as packed RGB
+fn load_png(path: &Path) -> Result<(Vec<u8>, (usize, usize))> {
+	let mut decoder = png::Decoder::new(File::open(path)?);
+	decoder.set_transformations(png::Transformations::EXPAND);
+
+	let (info, mut reader) = decoder.read_info()?;
+	let mut data = vec![0; info.buffer_size()];
+	reader.next_frame(&mut data)?;
+
+	let rgb = match info.color_type {
+		png::ColorType::RGB => data,
+		png::ColorType::RGBA => data.chunks(4).flat_map(|px| px[..3].to_vec()).collect(),
+		png::ColorType::Grayscale => data.iter().flat_map(|&v| vec![v; 3]).collect(),
+		png::ColorType::GrayscaleAlpha => data.chunks(2).flat_map(|px| vec![px[0]; 3]).collect(),
+		png::ColorType::Indexed => unreachable!("Palettes are expanded"),
+	};
+
+	Ok((rgb, (info.width as usize, info.height as usize)))
+}
+
+/// Marks mismatching pixels red on top of a dimmed copy of `actual`
+fn diff(actual: &[u8], expected: &[u8]) -> Vec<u8> {
+	actual
+		.chunks(3)
+		.zip(expected.chunks(3))
+		.flat_map(|(a, e)| {
+			if a == e {
+				a.iter().map(|v| v / 4).collect()
+			} else {
+				vec![0xff, 0x00, 0x00]
+			}
+		})
+		.collect()
+}
+
+/// Runs a case and compares it with its reference
+fn check(rom: &str, bless: bool) -> Result<()> {
+	let name = Path::new(rom).file_stem().unwrap().to_str().unwrap();
+	let reference_path = golden_dir().join(format!("{}.png", name));
+
+	// Unimplemented instructions panic, don't let them end the suite
+	let rom_path = find_rom(rom).unwrap();
+	let (actual, size) = match panic::catch_unwind(AssertUnwindSafe(|| run(&rom_path))) {
+		Ok(frame) => frame?,
+		Err(_) => return Err(eyre!("panicked")),
+	};
+
+	if bless {
+		return screenshot::save_png(&reference_path, &actual, size, 1);
+	}
+	if !reference_path.exists() {
+		return Err(eyre!("no reference picture"));
+	}
+
+	let (expected, expected_size) = load_png(&reference_path)?;
+	if (&actual, size) == (&expected, expected_size) {
+		return Ok(());
+	}
+
+	let out = output_dir();
+	fs::create_dir_all(&out)?;
+	screenshot::save_png(out.join(format!("{}-actual.png", name)), &actual, size, 1)?;
+	if size == expected_size {
+		let diff = diff(&actual, &expected);
+		screenshot::save_png(out.join(format!("{}-diff.png", name)), &diff, size, 1)?;
+	}
+
+	Err(eyre!("picture differs from the reference"))
+}
+
+#[test]
+fn test_golden_images() {
+	let bless = env::var_os("KUNZITE_BLESS").is_some();
+
+	let mut failures = Vec::new();
+	for rom in ROMS {
+		if find_rom(rom).is_none() {
+			eprintln!("skipping {}, set KUNZITE_TEST_ROMS to run it", rom);
+			continue;
+		}
+
+		let expected_fail = EXPECTED_FAIL.contains(rom);
+		match check(rom, bless && !expected_fail) {
+			Ok(()) if expected_fail => {
+				failures.push(format!("{}: passes, take it off EXPECTED_FAIL", rom))
+			}
+			Ok(()) => {}
+			Err(err) if expected_fail => eprintln!("{}: {} (expected)", rom, err),
+			Err(err) => failures.push(format!("{}: {}", rom, err)),
+		}
+	}
+
+	assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,119 @@
+//! Running without a window
+
+use crate::{
+	audio::{CLOCK_RATE, DEFAULT_SAMPLE_RATE},
+	cli::Args,
+	gb::{Gb, FRAME_CYCLES},
+	gbs::GbsPlayer,
+	link::Link,
+	palette::Palette,
+	recorder::Recorder,
+	screenshot,
+	wav::WavRecorder,
+};
+use color_eyre::Result;
+use std::time::Instant;
+
+/// Runs the emulator for the jobs given on the command line
+pub fn run(args: &Args) -> Result<()> {
+	let mut gb = Gb::create();
+	gb.cpu.memory.serial.connect(args.serial.device()?);
+	// Before the game starts, so the log holds every write
+	if args.record_vgm.is_some() {
+		gb.cpu.memory.audio.start_vgm();
+	}
+
+	let mut player = if args.gbs() {
+		let mut player = GbsPlayer::insert(&mut gb, &args.rom)?;
+		if let Some(track) = args.track {
+			player.select_track(&mut gb, track)?;
+		}
+		Some(player)
+	} else {
+		gb.insert_rom(&args.rom)?;
+		gb.boot();
+		None
+	};
+	// Only the first Game Boy is recorded
+	let mut link = match &args.link {
+		Some(path) => Some(Link::insert(&mut gb, path)?),
+		None => None,
+	};
+
+	// The default grayscale, so results don't depend on GUI settings
+	let palette = &Palette::builtin()[0];
+
+	let mut recorder = match &args.record {
+		Some(path) => Some(Recorder::create(path, gb.frame_size())?),
+		None => None,
+	};
+
+	let mut wav = match &args.record_audio {
+		Some(path) => {
+			// Fixed rate and no rate control, so the output is reproducible
+			let audio = &mut gb.cpu.memory.audio;
+			audio.set_sample_rate(DEFAULT_SAMPLE_RATE);
+			audio.set_stems(args.stems);
+			Some(WavRecorder::create(path, DEFAULT_SAMPLE_RATE, args.stems)?)
+		}
+		None => None,
+	};
+
+	let screenshot_frame = args.screenshot.as_ref().map_or(0, |(frame, _)| *frame);
+	let frames = args.frames.max(screenshot_frame);
+
+	let start = Instant::now();
+	for frame in 0..=frames {
+		if frame > 0 {
+			match (&mut player, &mut link) {
+				(Some(player), _) => player.run_frame(&mut gb),
+				(None, Some(link)) => link.run_frame(&mut gb),
+				(None, None) => gb.run_frame(),
+			};
+		}
+
+		if let Some((at, path)) = &args.screenshot {
+			if *at == frame {
+				screenshot::save_png(path, &gb.frame_rgb(palette), gb.frame_size(), args.scale)?;
+			}
+		}
+
+		// Frames 1 to N, the one on screen at power on is blank
+		if let Some(recorder) = &mut recorder {
+			if frame > 0 && frame <= args.frames {
+				recorder.frame(&gb.frame_rgb(palette))?;
+			}
+		}
+
+		if let Some(wav) = &mut wav {
+			if frame > 0 && frame <= args.frames {
+				let audio = &mut gb.cpu.memory.audio;
+				let samples = audio.take_samples();
+				let stems = audio.take_stems();
+				wav.write(&samples, stems.as_deref())?;
+			}
+		}
+	}
+
+	if args.benchmark {
+		let elapsed = start.elapsed();
+		let fps = frames as f64 / elapsed.as_secs_f64();
+		let speed = fps * FRAME_CYCLES as f64 / CLOCK_RATE as f64;
+		println!(
+			"{} frames in {:.2?}, {:.1} fps ({:.1}x speed)",
+			frames, elapsed, fps, speed
+		);
+	}
+
+	if let Some(recorder) = recorder {
+		recorder.finish()?;
+	}
+	if let Some(wav) = wav {
+		wav.finish()?;
+	}
+	if let (Some(path), Some(vgm)) = (&args.record_vgm, gb.cpu.memory.audio.stop_vgm()) {
+		vgm.save(path)?;
+	}
+
+	Ok(())
+}
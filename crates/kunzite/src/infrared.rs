@@ -0,0 +1,104 @@
+//! Infrared port (CGB)
+//!
+//! Games blink the LED and time the pulses seen by the sensor of the other
+//! Game Boy, so the two have to run in step. Only machines in the same
+//! process can be pointed at each other, see [`crate::link`].
+
+use std::{cell::RefCell, rc::Rc};
+
+/// What the infrared port is pointed at
+pub trait InfraredDevice {
+	/// Our LED turned on or off
+	fn set_led(&mut self, on: bool);
+
+	/// Whether light from the other side reaches the sensor
+	fn light(&self) -> bool;
+}
+
+/// LEDs of two Game Boys facing each other
+pub struct InfraredPort {
+	leds: Rc<RefCell<[bool; 2]>>,
+	side: usize,
+}
+
+/// Points two ports at each other, give one end to each Game Boy
+pub fn infrared_link() -> (InfraredPort, InfraredPort) {
+	let leds = Rc::new(RefCell::new([false; 2]));
+
+	(
+		InfraredPort {
+			leds: leds.clone(),
+			side: 0,
+		},
+		InfraredPort { leds, side: 1 },
+	)
+}
+
+impl InfraredDevice for InfraredPort {
+	fn set_led(&mut self, on: bool) {
+		self.leds.borrow_mut()[self.side] = on;
+	}
+
+	fn light(&self) -> bool {
+		self.leds.borrow()[1 - self.side]
+	}
+}
+
+/// Infrared communications port [FF56]
+#[derive(Default)]
+pub struct Infrared {
+	/// Bit 0 turns the LED on, bits 6 and 7 both set enable reading
+	rp: u8,
+	device: Option<Box<dyn InfraredDevice>>,
+}
+
+impl Infrared {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn connect(&mut self, device: Box<dyn InfraredDevice>) {
+		self.device = Some(device);
+	}
+
+	pub fn read(&self) -> u8 {
+		let reading = self.rp & 0xC0 == 0xC0;
+		let light = reading && self.device.as_ref().map_or(false, |device| device.light());
+
+		// Bit 1 goes low while light is received, bits 2-5 are unused
+		self.rp | 0x3C | if light { 0x00 } else { 0x02 }
+	}
+
+	pub fn write(&mut self, val: u8) {
+		self.rp = val & 0xC1;
+
+		if let Some(device) = &mut self.device {
+			device.set_led(val & 0x01 > 0);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_link() {
+		let (a, b) = infrared_link();
+		let (mut first, mut second) = (Infrared::new(), Infrared::new());
+		first.connect(Box::new(a));
+		second.connect(Box::new(b));
+
+		first.write(0x01);
+		// Reading is disabled
+		assert_eq!(second.read(), 0x3E);
+		second.write(0xC0);
+		assert_eq!(second.read(), 0xFC);
+
+		first.write(0x00);
+		assert_eq!(second.read(), 0xFE);
+		// Our own LED isn't seen
+		second.write(0xC1);
+		assert_eq!(second.read(), 0xFF);
+	}
+}
@@ -0,0 +1,124 @@
+//! Joypad input
+
+/// A button on the Game Boy
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Button {
+	Right,
+	Left,
+	Up,
+	Down,
+	A,
+	B,
+	Select,
+	Start,
+}
+
+impl Button {
+	/// Bit of the button in [`Joypad::pressed`], the d-pad sits in the
+	/// lower nibble and the action buttons in the upper one
+	fn mask(self) -> u8 {
+		match self {
+			Button::Right => 0x01,
+			Button::Left => 0x02,
+			Button::Up => 0x04,
+			Button::Down => 0x08,
+			Button::A => 0x10,
+			Button::B => 0x20,
+			Button::Select => 0x40,
+			Button::Start => 0x80,
+		}
+	}
+}
+
+/// Most joypads that can be connected at once, through the SGB
+pub const MAX_PLAYERS: usize = 4;
+
+/// Joypad
+///
+/// Normally only player 1 is wired up, an SGB can multiplex up to four
+/// joypads after a MLT_REQ command.
+#[derive(Default)]
+pub struct Joypad {
+	/// P14/P15 line selection, bits 4 and 5 of P1 [FF00]
+	select: u8,
+	/// Currently held buttons per player, a set bit means pressed
+	pressed: [u8; MAX_PLAYERS],
+	/// Number of joypads being multiplexed: 1, 2 or 4
+	players: usize,
+	/// Joypad currently connected to P1
+	player: usize,
+	/// Joypad interrupt request
+	pub irq: bool,
+}
+
+impl Joypad {
+	pub fn new() -> Self {
+		Self {
+			select: 0x30,
+			players: 1,
+			..Self::default()
+		}
+	}
+
+	/// Presses or releases a button on one of the joypads
+	pub fn set_button(&mut self, player: usize, button: Button, pressed: bool) {
+		let before = self.lines();
+
+		if pressed {
+			self.pressed[player] |= button.mask();
+		} else {
+			self.pressed[player] &= !button.mask();
+		}
+
+		// The interrupt fires when a selected line goes low
+		if before & !self.lines() & 0x0F > 0 {
+			self.irq = true;
+		}
+	}
+
+	/// Sets the number of multiplexed joypads, as requested by MLT_REQ
+	pub fn set_players(&mut self, players: usize) {
+		if self.players != players {
+			self.players = players;
+			self.player = 0;
+		}
+	}
+
+	pub fn players(&self) -> usize {
+		self.players
+	}
+
+	/// State of the P10-P13 input lines, low means pressed
+	fn lines(&self) -> u8 {
+		let pressed = self.pressed[self.player];
+		let mut lines = 0x0F;
+
+		if self.select & 0x10 == 0 {
+			lines &= !pressed & 0x0F;
+		}
+		if self.select & 0x20 == 0 {
+			lines &= !(pressed >> 4) & 0x0F;
+		}
+
+		// With both lines deselected the SGB reports which joypad is
+		// connected, 0xF for player 1 down to 0xC for player 4
+		if self.players > 1 && self.select == 0x30 {
+			lines = 0x0F - self.player as u8;
+		}
+
+		lines
+	}
+
+	pub fn read(&self) -> u8 {
+		0xC0 | self.select | self.lines()
+	}
+
+	pub fn write(&mut self, val: u8) {
+		// Raising P15 moves on to the next joypad
+		if self.players > 1 && self.select & 0x20 == 0 && val & 0x20 > 0 {
+			self.player = (self.player + 1) % self.players;
+		}
+
+		self.select = val & 0x30;
+	}
+}
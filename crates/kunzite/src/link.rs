@@ -0,0 +1,129 @@
+//! Two Game Boys connected by a link cable
+//!
+//! Their infrared ports face each other as well, for CGB games.
+//!
+//! Both machines run in lockstep: after every instruction of the first one,
+//! the second one catches up until it is at least as far along. Neither gets
+//! more than an instruction ahead, which is as fine as the serial port is
+//! updated, and the order only depends on the instructions executed, so the
+//! same inputs always lead to the same exchange.
+
+use crate::{
+	gb::{Gb, FRAME_CYCLES},
+	infrared::infrared_link,
+	serial::link_cable,
+};
+use color_eyre::Result;
+use std::path::Path;
+
+/// The second Game Boy and the bookkeeping to keep it in step
+pub struct Link {
+	/// The Game Boy on the other end of the cable
+	pub gb: Gb,
+	/// How far the second Game Boy is ahead in normal speed T-cycles
+	balance: i64,
+}
+
+impl Link {
+	/// Connects `other` to `gb` with a link cable
+	pub fn new(gb: &mut Gb, mut other: Gb) -> Self {
+		let (a, b) = link_cable();
+		gb.cpu.memory.serial.connect(Box::new(a));
+		other.cpu.memory.serial.connect(Box::new(b));
+
+		let (a, b) = infrared_link();
+		gb.cpu.memory.infrared.connect(Box::new(a));
+		other.cpu.memory.infrared.connect(Box::new(b));
+
+		Self {
+			gb: other,
+			balance: 0,
+		}
+	}
+
+	/// Boots the game at `path` on a second Game Boy connected to `gb`
+	pub fn insert<P: AsRef<Path>>(gb: &mut Gb, path: P) -> Result<Self> {
+		let mut other = Gb::create();
+		other.insert_rom(path)?;
+		other.boot();
+
+		Ok(Self::new(gb, other))
+	}
+
+	/// Runs one instruction of `gb`, and the second Game Boy until it has
+	/// caught up
+	pub fn step(&mut self, gb: &mut Gb) -> u32 {
+		let cycles = gb.step();
+		self.balance -= gb.elapsed(cycles);
+
+		while self.balance < 0 {
+			let cycles = self.gb.step();
+			self.balance += self.gb.elapsed(cycles);
+		}
+
+		cycles
+	}
+
+	/// Runs until `gb` enters V-Blank, like [`Gb::run_frame`]
+	pub fn run_frame(&mut self, gb: &mut Gb) -> u32 {
+		let frame = gb.frame_count();
+		let mut cycles = 0;
+
+		while gb.frame_count() == frame && cycles < FRAME_CYCLES {
+			cycles += self.step(gb);
+		}
+
+		cycles
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gb::Model;
+
+	/// A cartridge that starts a transfer of `sb` with control `sc` and then
+	/// spins
+	fn transfer_rom(model: Model, sb: u8, sc: u8) -> Gb {
+		let mut rom = vec![0; 0x8000];
+		rom[0x100..0x10B].copy_from_slice(&[
+			0x3E, sb, // ld a, sb
+			0xE0, 0x01, // ldh (SB), a
+			0x3E, sc, // ld a, sc
+			0xE0, 0x02, // ldh (SC), a
+			0xC3, 0x08, 0x01, // jp $0108
+		]);
+
+		let mut gb = Gb::create();
+		gb.cpu.memory.insert_raw(rom, 0).unwrap();
+		gb.cpu.memory.set_model(model);
+		gb.boot();
+		gb
+	}
+
+	#[test]
+	fn test_exchange() {
+		let mut master = transfer_rom(Model::Dmg, 0x42, 0x81);
+		let mut link = Link::new(&mut master, transfer_rom(Model::Dmg, 0x99, 0x80));
+		link.run_frame(&mut master);
+
+		assert_eq!(master.cpu.memory.read(0xFF01), 0x99);
+		assert_eq!(link.gb.cpu.memory.read(0xFF01), 0x42);
+		// Both transfers are done
+		assert_eq!(master.cpu.memory.read(0xFF02), 0x7F);
+		assert_eq!(link.gb.cpu.memory.read(0xFF02), 0x7E);
+	}
+
+	#[test]
+	fn test_exchange_fast() {
+		// Several bits go by per instruction of the other side
+		let mut master = transfer_rom(Model::Cgb, 0x5A, 0x83);
+		let mut link = Link::new(&mut master, transfer_rom(Model::Cgb, 0xC3, 0x80));
+		link.run_frame(&mut master);
+
+		assert_eq!(master.cpu.memory.read(0xFF01), 0xC3);
+		assert_eq!(link.gb.cpu.memory.read(0xFF01), 0x5A);
+		assert_eq!(master.cpu.memory.read(0xFF02), 0x7F);
+		assert_eq!(link.gb.cpu.memory.read(0xFF02), 0x7C);
+	}
+}
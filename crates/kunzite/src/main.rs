@@ -3,16 +3,34 @@
 // #![deny(missing_docs)]
 #![feature(exclusive_range_pattern)]
 #![feature(const_panic)]
-#![feature(option_result_unwrap_unchecked)]
 
+pub mod cli;
 pub mod cpu;
 pub mod emulator;
 pub mod gb;
+pub mod gbs;
+#[cfg(test)]
+mod golden;
+pub mod headless;
+pub mod infrared;
+pub mod joypad;
+pub mod link;
 pub mod memory;
+#[cfg(test)]
+mod mooneye;
+pub mod palette;
 pub mod ppu;
+pub mod recorder;
 pub mod audio;
+pub mod screenshot;
+pub mod serial;
+pub mod sgb;
+pub mod timer;
 mod util;
+pub mod vgm;
+pub mod wav;
 
+use cli::Args;
 use color_eyre::Result;
 use emulator::Emulator;
 use gui::{run, Options};
@@ -21,7 +39,12 @@ use gui::{run, Options};
 fn main() -> Result<()> {
 	color_eyre::install()?;
 
+	let args = Args::from_env()?;
+	if args.headless() {
+		return headless::run(&args);
+	}
+
 	let options = Options::new("GB Emulator", 1000, 600);
 
-	run::<Emulator>(options)
+	run::<Emulator>(options, args)
 }
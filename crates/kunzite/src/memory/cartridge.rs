@@ -1,7 +1,7 @@
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
 use std::{fs::File, io::Read, path::Path};
 
-use crate::util::slice_to_string;
+use crate::{gb::Model, util::slice_to_string};
 
 #[derive(Default)]
 pub struct Cartridge {
@@ -153,6 +153,38 @@ impl Cartridge {
 		Ok(())
 	}
 
+	/// Inserts a ROM image that has no header, like the cartridge a GBS
+	/// file is mapped into
+	///
+	/// The ROM is banked like MBC1 and must be a power of two of 16 KiB
+	/// banks, from 2 up to 128 (2 MiB). Cartridge RAM starts out enabled.
+	pub fn insert_raw(&mut self, rom: Vec<u8>, ram_size: usize) -> Result<()> {
+		let banks = rom.len() / (16 * 1024);
+		if !banks.is_power_of_two() || !(2..=0x80).contains(&banks) {
+			return Err(eyre!("Invalid ROM size 0x{:X}", rom.len()));
+		}
+
+		self.rom = rom;
+		self.ram = vec![0; ram_size];
+		self.mbc_type = 0x01;
+		self.ram_enable = ram_size > 0;
+		self.bank_no_upper = 0;
+		self.bank_no_lower = 0;
+		self.num_rom_banks = banks as u8;
+		self.mode = false;
+		self.header = None;
+
+		Ok(())
+	}
+
+	/// The hardware the inserted cartridge wants to run on
+	pub fn model(&self) -> Model {
+		match &self.header {
+			Some(header) => header.model(),
+			None => Model::Dmg,
+		}
+	}
+
 	fn rom_bank_no(&self) -> u8 {
 		let bank_no = if self.mode {
 			self.bank_no_lower
@@ -168,6 +200,21 @@ impl Cartridge {
 		bank_no & (self.num_rom_banks - 1)
 	}
 
+	/// Whether there is any ROM to read from
+	pub fn inserted(&self) -> bool {
+		!self.rom.is_empty()
+	}
+
+	/// Offset into the ROM that an address in 0x0000-0x7FFF reads from
+	pub fn rom_offset(&self, addr: usize) -> usize {
+		match addr {
+			// ROM bank 00
+			0x0000..0x4000 => addr,
+			// ROM bank 01-7f
+			_ => (16 * 1024) * self.rom_bank_no() as usize + (addr & 0x3fff),
+		}
+	}
+
 	fn ram_bank_no(&self) -> u8 {
 		if self.mode {
 			self.bank_no_upper
@@ -177,16 +224,24 @@ impl Cartridge {
 	}
 }
 
+impl CartridgeHeader {
+	fn model(&self) -> Model {
+		// Bit 7 marks CGB support, 0xC0 means CGB only
+		if self.cgb & 0x80 > 0 {
+			Model::Cgb
+		} else if self.sgb == 0x03 && self.old_licesee == 0x33 {
+			// SGB functions are ignored unless the old licensee is 0x33
+			Model::Sgb
+		} else {
+			Model::Dmg
+		}
+	}
+}
+
 impl Cartridge {
 	pub fn read(&self, addr: usize) -> u8 {
 		match addr {
-			// ROM bank 00
-			0x0000..0x4000 => self.rom[addr as usize],
-			// ROM bank 01-7f
-			0x4000..0x8000 => {
-				let offset = (16 * 1024) * self.rom_bank_no() as usize;
-				self.rom[(addr & 0x3fff) as usize + offset]
-			}
+			0x0000..0x8000 => self.rom[self.rom_offset(addr)],
 			// RAM bank 00-03
 			0xA000..0xC000 => {
 				if !self.ram_enable {
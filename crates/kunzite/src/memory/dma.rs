@@ -0,0 +1,155 @@
+/// Number of bytes copied by a single OAM DMA transfer
+const TRANSFER_LENGTH: u8 = 0xA0;
+
+/// M-cycles between writing to 0xFF46 and the first byte being copied
+const STARTUP_DELAY: u8 = 1;
+
+/// The bus a given address is wired to while a DMA transfer is running
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Bus {
+	/// Cartridge ROM/RAM and work RAM
+	External,
+	/// VRAM
+	Video,
+}
+
+impl Bus {
+	/// Returns the bus an address lives on, `None` for addresses that are
+	/// never in conflict with a transfer (OAM, IO and HRAM)
+	pub fn of(addr: u16) -> Option<Self> {
+		match addr {
+			0x8000..0xA000 => Some(Bus::Video),
+			0x0000..0xFE00 => Some(Bus::External),
+			_ => None,
+		}
+	}
+}
+
+/// OAM DMA controller
+///
+/// A transfer copies one byte per M-cycle from `XX00-XX9F` into OAM, where
+/// `XX` is the value written to 0xFF46. Writing the register while a
+/// transfer is running restarts it once the startup delay has elapsed, the
+/// old transfer keeps going until then.
+#[derive(Default)]
+pub struct Dma {
+	/// DMA Transfer and Start Address [FF46]
+	reg: u8,
+	/// Requested transfer waiting for its startup delay: (source, delay)
+	pending: Option<(u16, u8)>,
+	/// Source address of the running transfer
+	source: Option<u16>,
+	/// Index of the next byte to copy
+	index: u8,
+	/// Last byte put on the bus by the transfer
+	pub last_byte: u8,
+}
+
+impl Dma {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn read(&self) -> u8 {
+		self.reg
+	}
+
+	/// Requests a new transfer from `val << 8`
+	pub fn write(&mut self, val: u8) {
+		self.reg = val;
+
+		// Sources past work RAM are mirrored onto it, same as echo RAM
+		let src = match (val as u16) << 8 {
+			addr @ 0xE000..=0xFFFF => addr - 0x2000,
+			addr => addr,
+		};
+
+		self.pending = Some((src, STARTUP_DELAY));
+	}
+
+	/// Whether a transfer currently owns the bus
+	pub fn active(&self) -> bool {
+		self.source.is_some()
+	}
+
+	/// The bus the running transfer is reading from
+	pub fn bus(&self) -> Option<Bus> {
+		self.source.and_then(|src| Bus::of(src + self.index as u16))
+	}
+
+	/// Advances the controller by one M-cycle
+	///
+	/// Returns the source address and OAM offset of the byte to copy this
+	/// cycle, if any.
+	pub fn step(&mut self) -> Option<(u16, u8)> {
+		let copy = self.source.map(|src| {
+			let offset = self.index;
+			self.index += 1;
+			if self.index == TRANSFER_LENGTH {
+				self.source = None;
+			}
+			(src + offset as u16, offset)
+		});
+
+		if let Some((src, delay)) = self.pending {
+			if delay == 0 {
+				self.pending = None;
+				self.source = Some(src);
+				self.index = 0;
+			} else {
+				self.pending = Some((src, delay - 1));
+			}
+		}
+
+		copy
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_transfer_timing() {
+		let mut dma = Dma::new();
+		dma.write(0xC1);
+
+		// startup delay
+		assert_eq!(dma.step(), None);
+		assert_eq!(dma.step(), None);
+		assert!(dma.active());
+
+		for i in 0..TRANSFER_LENGTH {
+			assert_eq!(dma.step(), Some((0xC100 + i as u16, i)));
+		}
+
+		assert!(!dma.active());
+		assert_eq!(dma.read(), 0xC1);
+	}
+
+	#[test]
+	fn test_restart() {
+		let mut dma = Dma::new();
+		dma.write(0xC0);
+		for _ in 0..12 {
+			dma.step();
+		}
+
+		// the old transfer keeps running until the new one starts
+		dma.write(0xD0);
+		assert_eq!(dma.step(), Some((0xC00A, 0x0A)));
+		assert_eq!(dma.step(), Some((0xC00B, 0x0B)));
+		assert_eq!(dma.step(), Some((0xD000, 0x00)));
+	}
+
+	#[test]
+	fn test_echo_source() {
+		let mut dma = Dma::new();
+		dma.write(0xFE);
+		dma.step();
+		dma.step();
+
+		assert_eq!(dma.step(), Some((0xDE00, 0)));
+		assert_eq!(dma.bus(), Some(Bus::External));
+	}
+}
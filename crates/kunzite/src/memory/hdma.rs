@@ -0,0 +1,91 @@
+/// Bytes copied per block of a VRAM DMA transfer
+pub const BLOCK_LEN: u16 = 0x10;
+
+/// VRAM DMA controller (CGB)
+///
+/// Copies blocks of 16 bytes from ROM/RAM into VRAM, either all at once
+/// (general purpose) or one block per H-Blank.
+#[derive(Default)]
+pub struct Hdma {
+	/// Source address [FF51/FF52]
+	src: u16,
+	/// Destination address in VRAM [FF53/FF54]
+	dst: u16,
+	/// Blocks left to copy minus one [FF55 bits 0-6]
+	remaining: u8,
+	/// An H-Blank transfer is in progress
+	hblank: bool,
+}
+
+impl Hdma {
+	pub fn new() -> Self {
+		Self {
+			remaining: 0x7F,
+			..Self::default()
+		}
+	}
+
+	pub fn read(&self, addr: usize) -> u8 {
+		match addr {
+			// Bit 7 is cleared while an H-Blank transfer is active
+			0xFF55 if self.hblank => self.remaining,
+			0xFF55 => 0x80 | self.remaining,
+			// HDMA1-4 are write only
+			_ => 0xFF,
+		}
+	}
+
+	/// Writes a register, returns true if a general purpose transfer should
+	/// run right away
+	pub fn write(&mut self, addr: usize, val: u8) -> bool {
+		match addr {
+			0xFF51 => self.src = (self.src & 0x00FF) | (val as u16) << 8,
+			0xFF52 => self.src = (self.src & 0xFF00) | (val & 0xF0) as u16,
+			0xFF53 => self.dst = (self.dst & 0x00FF) | ((val & 0x1F) as u16) << 8,
+			0xFF54 => self.dst = (self.dst & 0xFF00) | (val & 0xF0) as u16,
+			0xFF55 => {
+				if self.hblank && val & 0x80 == 0 {
+					// Writing bit 7 = 0 stops an H-Blank transfer
+					self.hblank = false;
+					return false;
+				}
+
+				self.remaining = val & 0x7F;
+				self.hblank = val & 0x80 > 0;
+
+				return !self.hblank;
+			}
+			_ => unreachable!("Unexpected address: 0x{:04x}", addr),
+		}
+
+		false
+	}
+
+	/// Whether a block should be copied on the next H-Blank
+	pub fn hblank_active(&self) -> bool {
+		self.hblank
+	}
+
+	/// Number of blocks left in the current transfer
+	pub fn blocks(&self) -> u16 {
+		self.remaining as u16 + 1
+	}
+
+	/// Takes the next block to copy
+	///
+	/// Returns the source address and the offset into VRAM of the block.
+	pub fn next_block(&mut self) -> (u16, u16) {
+		let block = (self.src, self.dst & 0x1FF0);
+
+		self.src = self.src.wrapping_add(BLOCK_LEN);
+		self.dst = self.dst.wrapping_add(BLOCK_LEN) & 0x1FF0;
+
+		// The length wraps to 0x7F once the last block is done
+		self.remaining = self.remaining.wrapping_sub(1) & 0x7F;
+		if self.remaining == 0x7F {
+			self.hblank = false;
+		}
+
+		block
+	}
+}
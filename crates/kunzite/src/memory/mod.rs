@@ -1,22 +1,81 @@
 //! Memory module
+//!
+//! Most reads hit ROM, work RAM or HRAM, so those go through a table with an
+//! entry for every 256 byte page of the address space that says where the
+//! page is mapped. The table holds offsets rather than pointers, so the
+//! machine can still be moved around, and is rebuilt whenever a bank is
+//! switched. Everything else, and anything while OAM DMA is running, takes
+//! the slow path that decodes the full address.
 
 mod cartridge;
+mod dma;
+mod hdma;
 
-use self::cartridge::Cartridge;
-use crate::{audio::Audio, ppu::PPU};
+use self::{
+	cartridge::Cartridge,
+	dma::{Bus, Dma},
+	hdma::{Hdma, BLOCK_LEN},
+};
+use crate::{
+	audio::Audio, gb::Model, infrared::Infrared, joypad::Joypad, ppu::PPU, serial::Serial,
+	sgb::Sgb, timer::Timer,
+};
+use color_eyre::Result;
+use std::path::Path;
+
+/// Size of a page of the address space
+const PAGE_LEN: usize = 0x100;
+
+/// Where reads from a page of the address space go
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Page {
+	/// Cartridge ROM, starting at this offset
+	Rom(u32),
+	/// Work RAM, starting at this offset
+	Wram(u32),
+	/// IO registers, HRAM and the interrupt enable register
+	High,
+	/// Full address decoding
+	Slow,
+}
 
 /// Memory
 pub struct Memory {
-	pub cartridge: Cartridge,
-	ram: [u8; 0x2000],
+	/// Insert cartridges with [`Memory::insert_rom`] or
+	/// [`Memory::insert_raw`], which keep the page table up to date
+	cartridge: Cartridge,
+	/// Where each page of the address space reads from
+	pages: [Page; 0x100],
+	/// Work RAM, bank 0 followed by the switchable banks 1-7 (CGB)
+	ram: [u8; 0x8000],
+	/// WRAM bank select (CGB) [FF70]
+	svbk: u8,
 	hram: [u8; 0x7F],
-	_serial_io: [u8; 0x4C],
 	pub ppu: PPU,
 	pub audio: Audio,
+	pub joypad: Joypad,
+	pub serial: Serial,
+	pub timer: Timer,
+	/// Infrared port (CGB)
+	pub infrared: Infrared,
+	/// Super Game Boy, listening in on P1
+	pub sgb: Option<Sgb>,
+	/// OAM DMA controller
+	dma: Dma,
+	/// VRAM DMA controller (CGB)
+	hdma: Hdma,
+	/// Speed switch (CGB) [FF4D]
+	key1: u8,
+	/// Running in CGB double speed mode
+	double_speed: bool,
+	/// CPU T-cycles the cpu has to sit out for VRAM DMA or a speed switch
+	stall: u32,
 	/// Interrupt flag
 	pub int_flag: u8,
 	/// Interrupt enable
 	pub int_enable: u8,
+	/// The hardware being emulated
+	model: Model,
 }
 
 impl Default for Memory {
@@ -30,86 +89,329 @@ impl Memory {
 
 	/// Create a new memory instance
 	pub fn new() -> Self {
-		Self {
+		let mut memory = Self {
 			cartridge: Cartridge::new(),
-			ram: [0; 0x2000],
-			_serial_io: [0; 0x4C],
+			pages: [Page::Slow; 0x100],
+			ram: [0; 0x8000],
+			svbk: 0,
 			int_flag: 0,
 			int_enable: 0,
 			ppu: PPU::new(),
 			audio: Audio::new(),
+			joypad: Joypad::new(),
+			serial: Serial::new(),
+			timer: Timer::new(),
+			infrared: Infrared::new(),
+			sgb: None,
+			dma: Dma::new(),
+			hdma: Hdma::new(),
+			key1: 0,
+			double_speed: false,
+			stall: 0,
 			hram: [0; 0x7F],
+			model: Model::Dmg,
+		};
+		memory.pages[0xFF] = Page::High;
+		memory.map_wram();
+
+		memory
+	}
+
+	/// Inserts the cartridge at `path`
+	pub fn insert_rom<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+		self.cartridge.insert_rom(path)?;
+		self.map_rom();
+
+		Ok(())
+	}
+
+	/// Inserts a ROM image without a header, see [`Cartridge::insert_raw`]
+	pub fn insert_raw(&mut self, rom: Vec<u8>, ram_size: usize) -> Result<()> {
+		self.cartridge.insert_raw(rom, ram_size)?;
+		self.map_rom();
+
+		Ok(())
+	}
+
+	pub fn cartridge(&self) -> &Cartridge {
+		&self.cartridge
+	}
+
+	/// Points the ROM pages at the banks currently mapped
+	fn map_rom(&mut self) {
+		if !self.cartridge.inserted() {
+			return;
+		}
+
+		for page in 0x00..0x80 {
+			let offset = self.cartridge.rom_offset(page * PAGE_LEN);
+			self.pages[page] = Page::Rom(offset as u32);
+		}
+	}
+
+	/// Points the work RAM pages and their echo at the banks currently mapped
+	fn map_wram(&mut self) {
+		for page in 0xC0..0xFE {
+			let offset = self.wram_addr(page * PAGE_LEN);
+			self.pages[page] = Page::Wram(offset as u32);
+		}
+	}
+
+	/// Switches the hardware being emulated
+	pub fn set_model(&mut self, model: Model) {
+		self.model = model;
+		self.ppu.set_cgb(model == Model::Cgb);
+		self.audio.set_cgb(model == Model::Cgb);
+		self.serial.set_cgb(model == Model::Cgb);
+		// Only the CGB switches WRAM banks
+		self.map_wram();
+		self.sgb = if model == Model::Sgb {
+			Some(Sgb::new())
+		} else {
+			None
+		};
+	}
+
+	pub fn model(&self) -> Model {
+		self.model
+	}
+
+	/// Whether a STOP instruction will switch speeds
+	pub fn speed_switch_armed(&self) -> bool {
+		self.model == Model::Cgb && self.key1 & 0x1 > 0
+	}
+
+	/// Toggles double speed mode, triggered by STOP
+	pub fn switch_speed(&mut self) {
+		self.double_speed = !self.double_speed;
+		self.key1 = 0;
+		// The cpu is paused for 2050 M-cycles while the clock settles
+		self.stall += 2050 * 4;
+	}
+
+	pub fn double_speed(&self) -> bool {
+		self.double_speed
+	}
+
+	/// Returns the T-cycles the cpu has to wait before continuing
+	pub fn take_stall(&mut self) -> u32 {
+		std::mem::take(&mut self.stall)
+	}
+
+	fn write_p1(&mut self, val: u8) {
+		self.joypad.write(val);
+
+		if let Some(sgb) = &mut self.sgb {
+			sgb.write_p1(val);
+			self.joypad.set_players(sgb.players());
+		}
+	}
+
+	/// Writes to the cartridge ROM go to the MBC, all but RAM enable may
+	/// switch banks
+	fn write_mbc(&mut self, addr: usize, val: u8) {
+		self.cartridge.write(addr, val);
+		if addr >= 0x2000 {
+			self.map_rom();
 		}
 	}
 
-	fn dma(&mut self, val: u8) {
-		let src_base = (val as u16) << 8;
-		let dst_base = 0xFE00;
+	fn write_svbk(&mut self, val: u8) {
+		self.svbk = val & 0x07;
+		self.map_wram();
+	}
+
+	fn read_key1(&self) -> u8 {
+		0x7E | (self.double_speed as u8) << 7 | self.key1
+	}
 
-		if src_base >= 0x8000 && src_base < 0xE000 {
-			println!("DMA addr: 0x{:04X}", src_base);
-			for i in 0..0xA0 {
-				let tmp = self.read(src_base + i);
-				self.write(dst_base + i, tmp);
+	fn write_hdma(&mut self, addr: usize, val: u8) {
+		// General purpose transfers copy everything at once
+		if self.hdma.write(addr, val) {
+			for _ in 0..self.hdma.blocks() {
+				self.hdma_block();
 			}
 		}
 	}
 
-	pub fn get(&self, addr: u16) -> Option<u8> {
-		let addr = addr as usize;
-		let val = match addr {
+	/// Copies one block of VRAM DMA
+	fn hdma_block(&mut self) {
+		let (src, dst) = self.hdma.next_block();
+		for i in 0..BLOCK_LEN {
+			let val = self.get_unlocked(src.wrapping_add(i) as usize);
+			self.ppu.hdma_write(dst + i, val);
+		}
+
+		// 8 M-cycles per block, which takes twice as many cpu cycles in
+		// double speed mode
+		self.stall += if self.double_speed { 64 } else { 32 };
+	}
+
+	/// Offset into work RAM of an address in 0xC000-0xFDFF
+	fn wram_addr(&self, addr: usize) -> usize {
+		let bank = if addr & 0x1000 == 0 {
+			0
+		} else if self.model == Model::Cgb {
+			// Bank 0 can't be mapped to the switchable area
+			(self.svbk as usize & 0x7).max(1)
+		} else {
+			1
+		};
+
+		bank * 0x1000 + (addr & 0x0FFF)
+	}
+
+	/// Runs the OAM DMA controller for the given number of M-cycles
+	fn update_dma(&mut self, cycles: u8) {
+		for _ in 0..cycles {
+			if let Some((src, offset)) = self.dma.step() {
+				let val = self.get_unlocked(src as usize);
+				self.dma.last_byte = val;
+				self.ppu.dma_write(offset, val);
+			}
+		}
+	}
+
+	/// Returns what the cpu sees on the bus while OAM DMA is running, `None`
+	/// if the access is unaffected by the transfer
+	fn dma_conflict(&self, addr: u16) -> Option<u8> {
+		if !self.dma.active() {
+			return None;
+		}
+
+		match addr {
+			0xFE00..0xFF00 => Some(0xFF),
+			_ => match Bus::of(addr) {
+				Some(bus) if Some(bus) == self.dma.bus() => Some(self.dma.last_byte),
+				_ => None,
+			},
+		}
+	}
+
+	/// Reads memory ignoring any bus conflicts
+	fn get_unlocked(&self, addr: usize) -> u8 {
+		match addr {
 			0x0000..0x8000 => self.cartridge.read(addr), // cartrige rom
 			0x8000..0xA000 => self.ppu.read(addr),       // vram
 			0xA000..0xC000 => self.cartridge.read(addr), // switchable ram bank
-			0xC000..0xE000 => self.ram[addr & 0x1FFF],   // internal ram
-			0xE000..0xFE00 => self.ram[(addr - 0x2000) & 0x1FFF], // copy of internal ram
+			0xC000..0xFE00 => self.ram[self.wram_addr(addr)], // internal ram and its copy
 			0xFE00..0xFEA0 => self.ppu.read(addr),       // sprite attrib memory
-			0xFEA0..0xFF0F => 0,                         // prohibited
+			0xFEA0..0xFF00 => 0,                         // prohibited
+			0xFF00 => self.joypad.read(),                // Joypad
+			0xFF01..0xFF03 => self.serial.read(addr),    // Serial
+			0xFF04..0xFF08 => self.timer.read(addr),     // Timer
+			0xFF03 | 0xFF08..0xFF0F => 0xFF,             // unmapped
 			0xFF0F => self.int_flag,                     // Interrupt flag
 			0xFF10..0xFF40 => self.audio.read(addr),     // Audio
+			0xFF46 => self.dma.read(),                   // DMA
 			0xFF40..0xFF4C => self.ppu.read(addr),       // PPU (actually io but only need ppu atm)
-			0xFF4C..0xFF80 => 0,                         // ??? unused
+			0xFF4D if self.model == Model::Cgb => self.read_key1(), // Speed switch
+			0xFF4F => self.ppu.read(addr),               // VRAM bank
+			0xFF51..0xFF56 if self.model == Model::Cgb => self.hdma.read(addr), // VRAM DMA
+			0xFF56 if self.model == Model::Cgb => self.infrared.read(), // Infrared
+			0xFF68..0xFF6D => self.ppu.read(addr),       // CGB palettes
+			0xFF70 if self.model == Model::Cgb => 0xF8 | self.svbk, // WRAM bank
+			0xFF4C..0xFF80 => 0xFF,                      // unmapped
 			0xFF80..0xFFFF => self.hram[addr & 0x7f],    // HRAM
 			0xFFFF => self.int_enable,                   // Interrupt enable
-			_ => return None,
-		};
-
-		Some(val)
+			_ => unreachable!("Unexpected address: 0x{:04x}", addr),
+		}
 	}
 
 	pub fn read(&self, addr: u16) -> u8 {
-		unsafe { self.get(addr).unwrap_unchecked() }
+		// OAM DMA may own the bus, which only the slow path knows about
+		if !self.dma.active() {
+			let offset = addr as usize % PAGE_LEN;
+			match self.pages[addr as usize / PAGE_LEN] {
+				Page::Rom(base) => return self.cartridge.rom[base as usize + offset],
+				Page::Wram(base) => return self.ram[base as usize + offset],
+				Page::High if (0x80..0xFF).contains(&offset) => return self.hram[offset & 0x7f],
+				Page::High | Page::Slow => {}
+			}
+		}
+
+		if let Some(val) = self.dma_conflict(addr) {
+			return val;
+		}
+
+		self.get_unlocked(addr as usize)
 	}
 
 	pub fn write(&mut self, addr: u16, val: u8) {
+		// Writes to a bus owned by OAM DMA are lost
+		if self.dma_conflict(addr).is_some() {
+			return;
+		}
+
 		let addr = addr as usize;
 		match addr {
-			0x0000..0x8000 => self.cartridge.write(addr, val), // cartrige rom
-			0x8000..0xA000 => self.ppu.write(addr, val),       // vram
+			0x0000..0x8000 => self.write_mbc(addr, val), // cartrige rom
+			0x8000..0xA000 => self.ppu.write(addr, val), // vram
 			0xA000..0xC000 => self.cartridge.write(addr, val), // switchable ram bank
-			0xC000..0xE000 => self.ram[addr & 0x1FFF] = val,   // internal ram
-			0xE000..0xFE00 => self.ram[(addr - 0x2000) & 0x1FFF] = val, // copy of internal ram
-			0xFE00..0xFEA0 => self.ppu.write(addr, val),       // sprite attrib memory
-			0xFEA0..0xFF0F => (),                              // prohibited
-			0xFF0F => self.int_flag = val,                     // Interrupt flag
-			0xFF10..0xFF40 => self.audio.write(addr, val),     // Audio
-			0xFF46 => self.dma(val),                           // DMA
-			0xFF40..0xFF4C => self.ppu.write(addr, val),       // PPU
-			0xFF4C..0xFF80 => (),                              // ???
-			0xFF80..0xFFFF => self.hram[addr & 0x7f] = val,    // HRAM
-			0xFFFF => self.int_enable = val,                   // Interrupt enable
+			0xC000..0xFE00 => self.ram[self.wram_addr(addr)] = val, // internal ram and its copy
+			0xFE00..0xFEA0 => self.ppu.write(addr, val), // sprite attrib memory
+			0xFEA0..0xFF00 => (),                        // prohibited
+			0xFF00 => self.write_p1(val),                // Joypad
+			0xFF01..0xFF03 => self.serial.write(addr, val), // Serial
+			0xFF04..0xFF08 => self.timer.write(addr, val), // Timer
+			0xFF03 | 0xFF08..0xFF0F => (),               // unmapped
+			0xFF0F => self.int_flag = val,               // Interrupt flag
+			0xFF10..0xFF40 => self.audio.write(addr, val), // Audio
+			0xFF46 => self.dma.write(val),               // DMA
+			0xFF40..0xFF4C => self.ppu.write(addr, val), // PPU
+			0xFF4D => self.key1 = val & 0x01,            // Speed switch
+			0xFF4F => self.ppu.write(addr, val),         // VRAM bank
+			0xFF51..0xFF56 if self.model == Model::Cgb => self.write_hdma(addr, val), // VRAM DMA
+			0xFF56 if self.model == Model::Cgb => self.infrared.write(val), // Infrared
+			0xFF68..0xFF6D => self.ppu.write(addr, val), // CGB palettes
+			0xFF70 => self.write_svbk(val),              // WRAM bank
+			0xFF4C..0xFF80 => (),                        // unmapped
+			0xFF80..0xFFFF => self.hram[addr & 0x7f] = val, // HRAM
+			0xFFFF => self.int_enable = val,             // Interrupt enable
 			_ => unreachable!("Unexpected address: 0x{:04x}", addr),
 		};
 	}
 
 	pub fn update(&mut self, tick: u8) {
 		// self.cartridge.update(tick); does nothing
-		self.ppu.update(tick);
-		self.audio.update(tick);
+		// The PPU and APU keep running at normal speed in double speed mode,
+		// the serial port and timer go with the cpu
+		let slow_tick = if self.double_speed { tick / 2 } else { tick };
+		self.ppu.update(slow_tick);
+		self.audio.update(slow_tick);
+		self.serial.update(tick);
+		self.timer.update(tick);
+		self.update_dma(tick / 4);
+
+		if self.ppu.entered_hblank {
+			self.ppu.entered_hblank = false;
+			if self.hdma.hblank_active() {
+				self.hdma_block();
+			}
+		}
 
 		if self.ppu.irq_vblank {
 			self.int_flag |= 0x1;
 			self.ppu.irq_vblank = false;
+
+			if let Some(sgb) = &mut self.sgb {
+				sgb.vblank(self.ppu.frame_buffer());
+			}
+		}
+
+		if self.timer.irq {
+			self.int_flag |= 0x4;
+			self.timer.irq = false;
+		}
+
+		if self.serial.irq {
+			self.int_flag |= 0x8;
+			self.serial.irq = false;
+		}
+
+		if self.joypad.irq {
+			self.int_flag |= 0x10;
+			self.joypad.irq = false;
 		}
 
 		if self.ppu.irq_lcdc {
@@ -118,3 +420,38 @@ impl Memory {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Checks the page table against the full address decoding
+	fn check_pages(memory: &Memory) {
+		for addr in 0..=0xFFFF {
+			let slow = memory.get_unlocked(addr as usize);
+			assert_eq!(memory.read(addr), slow, "Mismatch at {:04x}", addr);
+		}
+	}
+
+	#[test]
+	fn test_page_table() {
+		let mut memory = Memory::new();
+		// Every byte tells its bank and offset apart
+		let rom = (0..8 * 0x4000).map(|i| (i / 0x4000 * 0x20 + i % 0x20) as u8);
+		memory.insert_raw(rom.collect(), 0x2000).unwrap();
+		memory.set_model(Model::Cgb);
+
+		for bank in 0..8 {
+			memory.write(0xFF70, bank);
+			memory.write(0xD000 + bank as u16, bank);
+			memory.write(0xFF80 + bank as u16, bank);
+		}
+		check_pages(&memory);
+
+		memory.write(0x2000, 5);
+		memory.write(0xFF70, 3);
+		assert_eq!(memory.read(0x4000), 0xA0);
+		assert_eq!(memory.read(0xF003), 3);
+		check_pages(&memory);
+	}
+}
@@ -0,0 +1,95 @@
+//! Mooneye test suite
+//!
+//! The ROMs run until `LD B,B` and report success by leaving the Fibonacci
+//! numbers 3, 5, 8, 13, 21 and 34 in B, C, D, E, H and L.
+//!
+//! The ROMs are not part of the repository, point `KUNZITE_TEST_ROMS` at the
+//! `acceptance` directory of a mooneye-test-suite build. ROMs that aren't
+//! there are skipped.
+
+use crate::{
+	cpu::instruction::Register8,
+	gb::{Gb, FRAME_CYCLES},
+};
+use color_eyre::{eyre::eyre, Result};
+use std::{
+	env,
+	panic::{self, AssertUnwindSafe},
+	path::{Path, PathBuf},
+};
+
+/// `LD B,B`, used by test ROMs as a breakpoint when they are done
+const LD_B_B: u8 = 0x40;
+/// Give up on a ROM that never finishes after this many frames
+const MAX_FRAMES: u64 = 600;
+/// What B, C, D, E, H and L hold when a test passes
+const PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+/// Test ROMs, relative to `KUNZITE_TEST_ROMS`
+const ROMS: &[&str] = &[
+	"oam_dma/basic.gb",
+	"oam_dma/reg_read.gb",
+	"oam_dma/sources-GS.gb",
+	"oam_dma_restart.gb",
+	"oam_dma_start.gb",
+	"oam_dma_timing.gb",
+];
+
+/// Runs a ROM until `LD B,B` and checks the result it reports
+fn run(rom: &Path) -> Result<()> {
+	let mut gb = Gb::create();
+	gb.insert_rom(rom)?;
+	gb.boot();
+
+	let mut cycles = 0;
+	while gb.cpu.memory.read(gb.cpu.pc) != LD_B_B {
+		if cycles > MAX_FRAMES * FRAME_CYCLES as u64 {
+			return Err(eyre!("no LD B,B after {} frames", MAX_FRAMES));
+		}
+		cycles += gb.step() as u64;
+	}
+
+	let regs = [
+		Register8::B,
+		Register8::C,
+		Register8::D,
+		Register8::E,
+		Register8::H,
+		Register8::L,
+	];
+	let result = regs.map(|reg| gb.cpu.read(reg));
+	if result != PASSED {
+		return Err(eyre!("failed with registers {:02X?}", result));
+	}
+
+	Ok(())
+}
+
+#[test]
+fn test_mooneye() {
+	let roms = match env::var_os("KUNZITE_TEST_ROMS") {
+		Some(dir) => PathBuf::from(dir),
+		None => {
+			eprintln!("KUNZITE_TEST_ROMS is not set, skipping the mooneye tests");
+			return;
+		}
+	};
+
+	let mut failures = Vec::new();
+	for rom in ROMS {
+		let path = roms.join(rom);
+		if !path.exists() {
+			eprintln!("skipping {}", rom);
+			continue;
+		}
+
+		// Unimplemented instructions panic, don't let them end the suite
+		match panic::catch_unwind(AssertUnwindSafe(|| run(&path))) {
+			Ok(Ok(())) => {}
+			Ok(Err(err)) => failures.push(format!("{}: {}", rom, err)),
+			Err(_) => failures.push(format!("{}: panicked", rom)),
+		}
+	}
+
+	assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,118 @@
+//! Colourisation
+//!
+//! On DMG the PPU only produces shades 0-3 (lightest to darkest), these are
+//! turned into real colours here. CGB output is already coloured and only
+//! needs widening to 8 bits per channel.
+
+use color_eyre::{eyre::eyre, Result};
+use std::{fs, path::Path};
+
+/// Extension of palette files, in the format read by [`Palette::load`]
+pub const EXTENSION: &str = "pal";
+
+/// A set of four colours, one per DMG shade
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+	pub name: String,
+	pub colors: [[u8; 3]; 4],
+}
+
+impl Palette {
+	/// Creates a palette from four `0xRRGGBB` values, lightest first
+	pub fn new<T: Into<String>>(name: T, colors: [u32; 4]) -> Self {
+		let mut rgb = [[0; 3]; 4];
+		for (dst, src) in rgb.iter_mut().zip(colors.iter()) {
+			*dst = [(src >> 16) as u8, (src >> 8) as u8, *src as u8];
+		}
+
+		Self {
+			name: name.into(),
+			colors: rgb,
+		}
+	}
+
+	/// The palettes that ship with the emulator
+	pub fn builtin() -> Vec<Self> {
+		vec![
+			Self::new("Grayscale", [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]),
+			Self::new("DMG Green", [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+			Self::new("Pocket", [0xE0DBCD, 0xA89F94, 0x706B66, 0x2B2B26]),
+			Self::new("BGB", [0xE0F8D0, 0x88C070, 0x346856, 0x081820]),
+			Self::new("High Contrast", [0xFFFFFF, 0xB4B4B4, 0x3C3C3C, 0x000000]),
+			Self::new("Colour-blind", [0xF7F7F7, 0xE69F00, 0x0072B2, 0x1A1A1A]),
+		]
+	}
+
+	/// Loads user defined palettes from a `.pal` file
+	///
+	/// Each line holds a name followed by four colours, lightest first:
+	///
+	/// ```text
+	/// ; comments start with a semicolon
+	/// Ice = #E8F4FF #98C0E0 #405880 #101828
+	/// ```
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Self>> {
+		let text = fs::read_to_string(path)?;
+
+		text.lines()
+			.enumerate()
+			.map(|(no, line)| (no + 1, line.trim()))
+			.filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+			.map(|(no, line)| {
+				Self::parse(line).ok_or_else(|| eyre!("Invalid palette on line {}", no))
+			})
+			.collect()
+	}
+
+	fn parse(line: &str) -> Option<Self> {
+		let (name, colors) = line.split_once('=')?;
+
+		let mut parsed = [0; 4];
+		let mut count = 0;
+		for color in colors.split_whitespace() {
+			let hex = color.strip_prefix('#')?;
+			if hex.len() != 6 || count == 4 {
+				return None;
+			}
+			parsed[count] = u32::from_str_radix(hex, 16).ok()?;
+			count += 1;
+		}
+
+		if count != 4 {
+			return None;
+		}
+
+		Some(Self::new(name.trim(), parsed))
+	}
+
+	/// Colour of a shade
+	pub fn rgb(&self, shade: u8) -> [u8; 3] {
+		self.colors[(shade & 0x3) as usize]
+	}
+
+	/// Colourises a buffer of shades as packed RGB
+	pub fn to_rgb(&self, shades: &[u8]) -> Vec<u8> {
+		shades.iter().flat_map(|&shade| self.rgb(shade)).collect()
+	}
+
+	/// Colourises a buffer of shades as packed RGBA
+	pub fn to_rgba(&self, shades: &[u8]) -> Vec<u8> {
+		shades
+			.iter()
+			.flat_map(|&shade| {
+				let [r, g, b] = self.rgb(shade);
+				[r, g, b, 0xFF]
+			})
+			.collect()
+	}
+}
+
+/// Expands a 15-bit CGB color to 8 bits per channel
+pub fn rgb555(color: u16) -> [u8; 3] {
+	let expand = |c: u16| {
+		let c = (c & 0x1f) as u8;
+		c << 3 | c >> 2
+	};
+
+	[expand(color), expand(color >> 5), expand(color >> 10)]
+}
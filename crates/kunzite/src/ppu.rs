@@ -1,18 +1,52 @@
+use crate::palette::{rgb555, Palette};
+
 /// Width of screen in pixels.
 const SCREEN_W: u8 = 160;
 /// Height of screen in pixels.
 const SCREEN_H: u8 = 144;
 
+/// Number of pixels in a frame.
+const FRAME_LEN: usize = (SCREEN_W as usize) * (SCREEN_H as usize);
+
 #[derive(Copy, Clone, PartialEq)]
 enum BGPriority {
 	Color0,
 	Color123,
+	/// Color 1-3 of a tile with the CGB BG-to-OAM priority attribute set
+	Priority,
+}
+
+/// Rendering options that deviate from the hardware, for debugging and
+/// enhancement.
+///
+/// The defaults match real hardware, accuracy tests must run with them.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RenderOptions {
+	/// Draw the BG layer, independent of LCDC.
+	pub bg: bool,
+	/// Draw the window layer, independent of LCDC.
+	pub window: bool,
+	/// Draw sprites, independent of LCDC.
+	pub sprites: bool,
+	/// Only draw 10 sprites per scanline, turning this off removes flicker.
+	pub sprite_limit: bool,
+}
+
+impl Default for RenderOptions {
+	fn default() -> Self {
+		Self {
+			bg: true,
+			window: true,
+			sprites: true,
+			sprite_limit: true,
+		}
+	}
 }
 
 /// Pixel Processing Unit.
 pub struct PPU {
-	/// VRAM
-	vram: [u8; 0x2000],
+	/// VRAM, bank 1 is only used on CGB
+	vram: [u8; 0x4000],
 	/// OAM
 	oam: [u8; 0xa0],
 	/// LCD Control
@@ -27,8 +61,6 @@ pub struct PPU {
 	ly: u8,
 	/// LY Compare
 	lyc: u8,
-	/// DMA Transfer and Start Address
-	dma: u8,
 	/// Background Palette Data
 	bgp: u8,
 	/// Object Palette 0 Data
@@ -39,18 +71,42 @@ pub struct PPU {
 	wy: u8,
 	/// Window X Position minus 7
 	wx: u8,
+	/// VRAM Bank (CGB)
+	vbk: u8,
+	/// Background Palette Index (CGB)
+	bcps: u8,
+	/// Background Palette Memory (CGB)
+	bg_palette_ram: [u8; 0x40],
+	/// Sprite Palette Index (CGB)
+	ocps: u8,
+	/// Sprite Palette Memory (CGB)
+	obj_palette_ram: [u8; 0x40],
+	/// Object Priority Mode (CGB)
+	opri: u8,
+	/// Running in CGB mode
+	cgb: bool,
 	/// V-Blank interrupt request
 	pub irq_vblank: bool,
 	/// LCDC interrupt request
 	pub irq_lcdc: bool,
+	/// Entered H-Blank since the last update, drives H-Blank VRAM DMA
+	pub entered_hblank: bool,
 	/// Elapsed clocks in current mode
 	counter: u16,
-	/// Frame buffer
-	frame_buffer: [u8; (SCREEN_W as usize) * (SCREEN_H as usize)],
+	/// Frame buffer, one shade per pixel
+	frame_buffer: [u8; FRAME_LEN],
+	/// Frame buffer, one 15-bit color per pixel (CGB)
+	color_buffer: [u16; FRAME_LEN],
 	/// Current scanline
 	scanline: [u8; SCREEN_W as usize],
+	/// Current scanline colors (CGB)
+	scanline_color: [u16; SCREEN_W as usize],
 	/// Background priority
 	bg_prio: [BGPriority; SCREEN_W as usize],
+	/// Layer toggles and enhancements
+	options: RenderOptions,
+	/// Number of frames completed, counted at the start of V-Blank
+	frames: u64,
 }
 
 impl PPU {
@@ -60,11 +116,12 @@ impl PPU {
 	// 0x1000-0x17ff: Tile set #3
 	// 0x1800-0x1bff: Tile map #1
 	// 0x1c00-0x1fff: Tile map #2
+	// 0x2000-0x3fff: Bank 1, tile sets and BG map attributes (CGB)
 
 	/// Creates a new `PPU`
 	pub fn new() -> Self {
 		Self {
-			vram: [0; 0x2000],
+			vram: [0; 0x4000],
 			oam: [0; 0xa0],
 			lcdc: 0x80,
 			stat: 0x02,
@@ -72,23 +129,49 @@ impl PPU {
 			scx: 0,
 			ly: 0,
 			lyc: 0,
-			dma: 0,
 			bgp: 0,
 			obp0: 0,
 			obp1: 0,
 			wy: 0,
 			wx: 0,
+			vbk: 0,
+			bcps: 0,
+			bg_palette_ram: [0xff; 0x40],
+			ocps: 0,
+			obj_palette_ram: [0xff; 0x40],
+			opri: 0,
+			cgb: false,
 			irq_vblank: false,
 			irq_lcdc: false,
+			entered_hblank: false,
 			counter: 0,
 			scanline: [0; SCREEN_W as usize],
-			frame_buffer: [0; (SCREEN_W as usize) * (SCREEN_H as usize)],
+			scanline_color: [0; SCREEN_W as usize],
+			frame_buffer: [0; FRAME_LEN],
+			color_buffer: [0; FRAME_LEN],
 			bg_prio: [BGPriority::Color0; SCREEN_W as usize],
+			options: RenderOptions::default(),
+			frames: 0,
 		}
 	}
 
+	/// Switches between DMG and CGB behaviour.
+	pub fn set_cgb(&mut self, cgb: bool) {
+		self.cgb = cgb;
+	}
+
+	/// Returns the current rendering options.
+	pub fn options(&self) -> RenderOptions {
+		self.options
+	}
+
+	/// Changes the rendering options, see [`RenderOptions`].
+	pub fn set_options(&mut self, options: RenderOptions) {
+		self.options = options;
+	}
+
 	/// Fetches tile data from VRAM.
-	fn fetch_tile(&self, tile_no: u8, offset_y: u8, tile_data_sel: bool) -> (u8, u8) {
+	fn fetch_tile(&self, tile_no: u8, offset_y: u8, tile_data_sel: bool, bank: u8) -> (u8, u8) {
 		// Fetch tile data from tile set
 		let tile_data_addr = if tile_data_sel {
 			// Use tile set #1 (0x0000-0x07ff) and #2 (0x0800-0x0fff)
@@ -97,31 +180,47 @@ impl PPU {
 			// Use tile set #2 (0x0800-0x0fff) and #3 (0x1000-0x17ff)
 			(0x1000 as u16).wrapping_add(((tile_no as i8 as i16) << 4) as u16)
 		};
-		let row_addr = tile_data_addr + (offset_y << 1) as u16;
+		let row_addr =
+			(bank as usize) * 0x2000 + (tile_data_addr + (offset_y << 1) as u16) as usize;
 
-		let tile0 = self.vram[row_addr as usize];
-		let tile1 = self.vram[(row_addr + 1) as usize];
+		let tile0 = self.vram[row_addr];
+		let tile1 = self.vram[row_addr + 1];
 
 		(tile0, tile1)
 	}
 
-	/// Fetches BG or Window tile data from VRAM.
+	/// Fetches BG or Window tile data and attributes from VRAM.
 	fn fetch_bg_window_tile(
 		&self,
 		tile_x: u8,
 		tile_y: u8,
 		offset_y: u8,
 		tile_map_base: u16,
-	) -> (u8, u8) {
+	) -> ((u8, u8), u8) {
 		// Fetch tile index from tile map
 		let tile_map_addr = tile_map_base | ((tile_x & 0x1f) as u16 + ((tile_y as u16) << 5));
 		let tile_no = self.vram[tile_map_addr as usize];
 
-		self.fetch_tile(tile_no, offset_y, self.lcdc & 0x10 > 0)
+		// Attributes sit at the same address in bank 1
+		let attr = if self.cgb {
+			self.vram[0x2000 + tile_map_addr as usize]
+		} else {
+			0
+		};
+
+		let offset_y = if attr & 0x40 > 0 {
+			7 - offset_y
+		} else {
+			offset_y
+		};
+
+		let tile = self.fetch_tile(tile_no, offset_y, self.lcdc & 0x10 > 0, (attr >> 3) & 1);
+
+		(tile, attr)
 	}
 
 	/// Fetches BG tile data from VRAM.
-	fn fetch_bg_tile(&self, tile_x: u8, tile_y: u8, offset_y: u8) -> (u8, u8) {
+	fn fetch_bg_tile(&self, tile_x: u8, tile_y: u8, offset_y: u8) -> ((u8, u8), u8) {
 		// Fetch tile index from tile map
 		let tile_map_base = if self.lcdc & 0x8 > 0 { 0x1c00 } else { 0x1800 };
 
@@ -129,21 +228,23 @@ impl PPU {
 	}
 
 	/// Fetches Window tile data from VRAM.
-	fn fetch_window_tile(&self, tile_x: u8, tile_y: u8, offset_y: u8) -> (u8, u8) {
+	fn fetch_window_tile(&self, tile_x: u8, tile_y: u8, offset_y: u8) -> ((u8, u8), u8) {
 		// Fetch tile index from tile map
 		let tile_map_base = if self.lcdc & 0x40 > 0 { 0x1c00 } else { 0x1800 };
 
 		self.fetch_bg_window_tile(tile_x, tile_y, offset_y, tile_map_base)
 	}
 
-	/// Converts color number to brightness using palette.
+	/// Converts color number to a shade (0 lightest, 3 darkest) using palette.
 	fn map_color(&self, color_no: u8, palette: u8) -> u8 {
-		match (palette >> (color_no << 1)) & 0x3 {
-			0 => 0xff,
-			1 => 0xaa,
-			2 => 0x55,
-			3 | _ => 0x00,
-		}
+		(palette >> (color_no << 1)) & 0x3
+	}
+
+	/// Looks up a 15-bit color in CGB palette memory.
+	fn cgb_color(palette_ram: &[u8; 0x40], palette: u8, color_no: u8) -> u16 {
+		let ix = ((palette & 0x7) << 3 | color_no << 1) as usize;
+
+		u16::from_le_bytes([palette_ram[ix], palette_ram[ix + 1]]) & 0x7fff
 	}
 
 	/// Returns the color number at a given position from tile data.
@@ -164,33 +265,51 @@ impl PPU {
 		let mut offset_x = self.scx & 0x7;
 		let mut offset_y = self.scy.wrapping_add(self.ly) & 0x7;
 
-		let mut tile = self.fetch_bg_tile(tile_x, tile_y, offset_y);
+		let (mut tile, mut attr) = self.fetch_bg_tile(tile_x, tile_y, offset_y);
 
 		let mut window = false;
 
 		for x in 0..SCREEN_W {
 			// Check if window is enabled
-			if self.lcdc & 0x20 > 0 {
+			if self.lcdc & 0x20 > 0 && self.options.window {
 				if self.wy <= self.ly && self.wx == x + 7 {
 					tile_x = 0;
 					tile_y = (self.ly - self.wy) >> 3;
 					offset_x = 0;
 					offset_y = (self.ly - self.wy) & 0x7;
-					tile = self.fetch_window_tile(tile_x, tile_y, offset_y);
+					let (t, a) = self.fetch_window_tile(tile_x, tile_y, offset_y);
+					tile = t;
+					attr = a;
 					window = true;
 				}
 			}
 
-			let color_no = self.get_color_no(tile, 7 - offset_x);
-			let color = self.map_color(color_no, self.bgp);
+			let bitpos = if attr & 0x20 > 0 {
+				offset_x
+			} else {
+				7 - offset_x
+			};
+			// A hidden BG layer shows up as color 0 so sprites stay visible
+			let color_no = if window || self.options.bg {
+				self.get_color_no(tile, bitpos)
+			} else {
+				0
+			};
 
 			self.bg_prio[x as usize] = if color_no == 0 {
 				BGPriority::Color0
+			} else if attr & 0x80 > 0 {
+				BGPriority::Priority
 			} else {
 				BGPriority::Color123
 			};
 
-			self.scanline[x as usize] = color;
+			if self.cgb {
+				self.scanline_color[x as usize] =
+					Self::cgb_color(&self.bg_palette_ram, attr, color_no);
+			} else {
+				self.scanline[x as usize] = self.map_color(color_no, self.bgp);
+			}
 
 			offset_x += 1;
 
@@ -199,21 +318,74 @@ impl PPU {
 				offset_x = 0;
 				tile_x += 1;
 
-				if window {
-					tile = self.fetch_window_tile(tile_x, tile_y, offset_y);
+				let (t, a) = if window {
+					self.fetch_window_tile(tile_x, tile_y, offset_y)
 				} else {
-					tile = self.fetch_bg_tile(tile_x, tile_y, offset_y);
-				}
+					self.fetch_bg_tile(tile_x, tile_y, offset_y)
+				};
+				tile = t;
+				attr = a;
 			}
 		}
 	}
 
+	/// Clears the BG when it is disabled on DMG.
+	fn clear_bg(&mut self) {
+		self.scanline = [0; SCREEN_W as usize];
+		self.bg_prio = [BGPriority::Color0; SCREEN_W as usize];
+	}
+
+	/// Checks whether the BG pixel at `x` is drawn over a sprite pixel.
+	fn bg_over_obj(&self, x: u8, obj_prio: bool) -> bool {
+		// On CGB, clearing LCDC bit 0 puts sprites above everything
+		if self.cgb && self.lcdc & 0x1 == 0 {
+			return false;
+		}
+
+		match self.bg_prio[x as usize] {
+			BGPriority::Color0 => false,
+			BGPriority::Priority => true,
+			BGPriority::Color123 => obj_prio,
+		}
+	}
+
 	/// Renders sprites.
 	fn render_sprites(&mut self) {
-		let mut n_sprites = 0;
 		let height = if self.lcdc & 0x4 > 0 { 16 } else { 8 };
 
+		// Select sprites on this scanline in OAM order
+		let mut sprites = [0; 40];
+		let mut n_sprites = 0;
 		for i in 0..40 {
+			let sprite_y = self.oam[i << 2];
+
+			// Check if sprite is visible on this scanline
+			if sprite_y <= self.ly + 16 - height || sprite_y > self.ly + 16 {
+				continue;
+			}
+
+			// Up to 10 sprites can be rendered on one scanline
+			if n_sprites == 10 && self.options.sprite_limit {
+				break;
+			}
+
+			sprites[n_sprites] = i;
+			n_sprites += 1;
+		}
+
+		// DMG favours the leftmost sprite, CGB the first one in OAM unless
+		// OPRI asks for DMG behaviour. The sort is stable so ties are
+		// resolved by OAM order in both cases.
+		let sprites = &mut sprites[..n_sprites];
+		if !self.cgb || self.opri & 0x1 > 0 {
+			let oam = &self.oam;
+			sprites.sort_by_key(|&i| oam[(i << 2) + 1]);
+		}
+
+		// Pixels already claimed by a higher priority sprite
+		let mut claimed = [false; SCREEN_W as usize];
+
+		for &i in sprites.iter() {
 			// Parse OAM entry
 			let entry_addr = i << 2;
 			let sprite_y = self.oam[entry_addr];
@@ -228,17 +400,7 @@ impl PPU {
 			} else {
 				self.obp0
 			};
-
-			// Check if sprite is visible on this scanline
-			if sprite_y <= self.ly + 16 - height || sprite_y > self.ly + 16 {
-				continue;
-			}
-
-			// Up to 10 sprites can be rendered on one scanline
-			n_sprites += 1;
-			if n_sprites > 10 {
-				break;
-			}
+			let bank = if self.cgb { (flags >> 3) & 1 } else { 0 };
 
 			// Check if sprite is within the screen
 			if sprite_x == 0 || sprite_x > SCREEN_W + 8 - 1 {
@@ -266,7 +428,7 @@ impl PPU {
 			};
 
 			// Fetch tile data
-			let tile = self.fetch_tile(tile_no, offset_y, true);
+			let tile = self.fetch_tile(tile_no, offset_y, true, bank);
 
 			for offset_x in 0..8 {
 				if offset_x + sprite_x < 8 {
@@ -281,39 +443,101 @@ impl PPU {
 
 				let bitpos = if flip_x { offset_x } else { 7 - offset_x };
 				let color_no = self.get_color_no(tile, bitpos);
-				if color_no == 0 {
+				if color_no == 0 || claimed[x as usize] {
 					continue;
 				}
-				if self.bg_prio[x as usize] == BGPriority::Color123 && obj_prio {
+				claimed[x as usize] = true;
+
+				if self.bg_over_obj(x, obj_prio) {
 					continue;
 				}
-				let color = self.map_color(color_no, palette);
 
-				self.scanline[x as usize] = color;
+				if self.cgb {
+					self.scanline_color[x as usize] =
+						Self::cgb_color(&self.obj_palette_ram, flags, color_no);
+				} else {
+					self.scanline[x as usize] = self.map_color(color_no, palette);
+				}
 			}
 		}
 	}
 
 	/// Renders a scanline.
 	fn render_scanline(&mut self) {
-		if self.lcdc & 0x1 > 0 {
+		// On CGB, LCDC bit 0 is the BG priority switch instead
+		if self.cgb || self.lcdc & 0x1 > 0 {
 			self.render_bg();
+		} else {
+			self.clear_bg();
 		}
-		if self.lcdc & 0x2 > 0 {
+		if self.lcdc & 0x2 > 0 && self.options.sprites {
 			self.render_sprites();
 		}
 
-		for x in 0..SCREEN_W {
-			let ix = (x as usize) + (self.ly as usize) * (SCREEN_W as usize);
-			self.frame_buffer[ix] = self.scanline[x as usize];
+		let line = (self.ly as usize) * (SCREEN_W as usize);
+		let range = line..line + SCREEN_W as usize;
+		if self.cgb {
+			self.color_buffer[range].copy_from_slice(&self.scanline_color);
+		} else {
+			self.frame_buffer[range].copy_from_slice(&self.scanline);
 		}
 	}
 
+	/// Writes a byte copied by OAM DMA, bypassing the mode check.
+	pub fn dma_write(&mut self, offset: u8, val: u8) {
+		self.oam[offset as usize] = val;
+	}
+
+	/// Writes a byte copied by VRAM DMA into the current bank.
+	pub fn hdma_write(&mut self, offset: u16, val: u8) {
+		let ix = self.vram_addr(offset as usize);
+		self.vram[ix] = val;
+	}
+
 	/// Returns the current contents of the frame buffer.
+	///
+	/// Pixels are shades from 0 (lightest) to 3 (darkest), see
+	/// [`Palette`](crate::palette::Palette) for turning them into colours.
 	pub fn frame_buffer(&self) -> &[u8] {
 		&self.frame_buffer
 	}
 
+	/// Returns the current contents of the CGB frame buffer.
+	///
+	/// Pixels are 15-bit `xBBBBBGGGGGRRRRR` colors.
+	pub fn color_buffer(&self) -> &[u16] {
+		&self.color_buffer
+	}
+
+	/// Offset into VRAM of an address in the currently selected bank.
+	fn vram_addr(&self, addr: usize) -> usize {
+		(self.vbk as usize) * 0x2000 + (addr & 0x1fff)
+	}
+
+	/// Advances a BCPS/OCPS index if auto-increment is enabled.
+	fn increment_palette_index(index: u8) -> u8 {
+		if index & 0x80 > 0 {
+			0x80 | (index.wrapping_add(1) & 0x3f)
+		} else {
+			index
+		}
+	}
+
+	/// Whether the PPU is running in CGB mode.
+	pub fn cgb(&self) -> bool {
+		self.cgb
+	}
+
+	/// Number of frames completed since power on.
+	pub fn frame_count(&self) -> u64 {
+		self.frames
+	}
+
+	/// Read-only view of the PPU state for the debugger.
+	pub fn debug(&self) -> PpuDebug<'_> {
+		PpuDebug { ppu: self }
+	}
+
 	/// Checks LYC interrupt.
 	fn update_lyc_interrupt(&mut self) {
 		// LYC=LY coincidence interrupt
@@ -350,7 +574,7 @@ impl PPU {
 			0x8000..=0x9fff => {
 				// VRAM is inaccessible during pixel transfer
 				if self.stat & 0x3 != 3 {
-					self.vram[(addr & 0x1fff) as usize] = val
+					self.vram[self.vram_addr(addr)] = val
 				}
 			}
 
@@ -391,6 +615,25 @@ impl PPU {
 			0xff4a => self.wy = val,
 			0xff4b => self.wx = val,
 
+			// CGB registers
+			_ if !self.cgb => (),
+			0xff4f => self.vbk = val & 0x1,
+			0xff68 => self.bcps = val & 0xbf,
+			0xff69 => {
+				if self.stat & 0x3 != 3 {
+					self.bg_palette_ram[(self.bcps & 0x3f) as usize] = val;
+				}
+				self.bcps = Self::increment_palette_index(self.bcps);
+			}
+			0xff6a => self.ocps = val & 0xbf,
+			0xff6b => {
+				if self.stat & 0x3 != 3 {
+					self.obj_palette_ram[(self.ocps & 0x3f) as usize] = val;
+				}
+				self.ocps = Self::increment_palette_index(self.ocps);
+			}
+			0xff6c => self.opri = val & 0x1,
+
 			_ => unreachable!("Unexpected address: 0x{:04x}", addr),
 		}
 	}
@@ -401,7 +644,7 @@ impl PPU {
 			0x8000..=0x9fff => {
 				// VRAM is inaccessible during pixel transfer
 				if self.stat & 0x3 != 3 {
-					self.vram[(addr & 0x1fff) as usize]
+					self.vram[self.vram_addr(addr)]
 				} else {
 					0xff
 				}
@@ -424,13 +667,22 @@ impl PPU {
 			0xff43 => self.scx,
 			0xff44 => self.ly,
 			0xff45 => self.lyc,
-			0xff46 => self.dma,
 			0xff47 => self.bgp,
 			0xff48 => self.obp0,
 			0xff49 => self.obp1,
 			0xff4a => self.wy,
 			0xff4b => self.wx,
 
+			// CGB registers
+			_ if !self.cgb => 0xff,
+			0xff4f => 0xfe | self.vbk,
+			0xff68 => 0x40 | self.bcps,
+			0xff69 if self.stat & 0x3 != 3 => self.bg_palette_ram[(self.bcps & 0x3f) as usize],
+			0xff6a => 0x40 | self.ocps,
+			0xff6b if self.stat & 0x3 != 3 => self.obj_palette_ram[(self.ocps & 0x3f) as usize],
+			0xff69 | 0xff6b => 0xff,
+			0xff6c => 0xfe | self.opri,
+
 			_ => unreachable!("Unexpected address: 0x{:04x}", addr),
 		}
 	}
@@ -458,6 +710,7 @@ impl PPU {
 					self.counter -= 172;
 					// Transition to H-Blank mode
 					self.stat = self.stat & 0xf8;
+					self.entered_hblank = true;
 					self.update_mode_interrupt();
 				}
 			}
@@ -471,6 +724,7 @@ impl PPU {
 						// Transition to V-Blank mode
 						self.stat = (self.stat & 0xf8) | 1;
 						self.irq_vblank = true;
+						self.frames += 1;
 					} else {
 						// Transition to OAM Search mode
 						self.stat = (self.stat & 0xf8) | 2;
@@ -500,3 +754,167 @@ impl PPU {
 		}
 	}
 }
+
+/// A palette entry as seen by the debugger.
+#[derive(Copy, Clone)]
+pub enum Color {
+	/// Shade from 0 (lightest) to 3 (darkest) (DMG)
+	Shade(u8),
+	/// 15-bit `xBBBBBGGGGGRRRRR` color (CGB)
+	Rgb555(u16),
+}
+
+impl Color {
+	/// Converts to RGB, using `palette` for DMG shades.
+	pub fn rgb(self, palette: &Palette) -> [u8; 3] {
+		match self {
+			Color::Shade(shade) => palette.rgb(shade),
+			Color::Rgb555(color) => rgb555(color),
+		}
+	}
+}
+
+/// A decoded OAM entry.
+pub struct Sprite {
+	pub y: u8,
+	pub x: u8,
+	pub tile: u8,
+	pub flags: u8,
+}
+
+impl Sprite {
+	/// BG and window colors 1-3 are drawn over the sprite.
+	pub fn behind_bg(&self) -> bool {
+		self.flags & 0x80 > 0
+	}
+
+	pub fn flip_y(&self) -> bool {
+		self.flags & 0x40 > 0
+	}
+
+	pub fn flip_x(&self) -> bool {
+		self.flags & 0x20 > 0
+	}
+
+	/// OBP0 or OBP1 (DMG)
+	pub fn dmg_palette(&self) -> u8 {
+		(self.flags >> 4) & 1
+	}
+
+	/// VRAM bank of the tile (CGB)
+	pub fn bank(&self) -> u8 {
+		(self.flags >> 3) & 1
+	}
+
+	/// OBJ palette 0-7 (CGB)
+	pub fn cgb_palette(&self) -> u8 {
+		self.flags & 0x7
+	}
+}
+
+/// Read-only view of the PPU for VRAM viewers and other debugging tools.
+pub struct PpuDebug<'a> {
+	ppu: &'a PPU,
+}
+
+impl PpuDebug<'_> {
+	/// Number of tiles in a VRAM bank.
+	pub const TILES: usize = 384;
+
+	/// Whether the PPU is running in CGB mode.
+	pub fn cgb(&self) -> bool {
+		self.ppu.cgb
+	}
+
+	/// LCD Control
+	pub fn lcdc(&self) -> u8 {
+		self.ppu.lcdc
+	}
+
+	/// Returns (SCX, SCY).
+	pub fn scroll(&self) -> (u8, u8) {
+		(self.ppu.scx, self.ppu.scy)
+	}
+
+	/// Returns (WX, WY).
+	pub fn window(&self) -> (u8, u8) {
+		(self.ppu.wx, self.ppu.wy)
+	}
+
+	/// Returns the color number of a pixel of one of the 384 tiles in a
+	/// bank, numbered from 0x8000 onwards.
+	pub fn tile_pixel(&self, bank: u8, tile: usize, x: usize, y: usize) -> u8 {
+		let addr = (bank as usize) * 0x2000 + (tile << 4) + (y << 1);
+		let data = (self.ppu.vram[addr], self.ppu.vram[addr + 1]);
+
+		self.ppu.get_color_no(data, 7 - x as u8)
+	}
+
+	/// Returns the color number and attributes of a pixel of the BG or
+	/// window tile map, as selected by LCDC.
+	pub fn map_pixel(&self, window: bool, x: usize, y: usize) -> (u8, u8) {
+		let sel = if window { 0x40 } else { 0x8 };
+		let base = if self.ppu.lcdc & sel > 0 {
+			0x1c00
+		} else {
+			0x1800
+		};
+		let (tile_x, tile_y, offset_y) = ((x >> 3) as u8, (y >> 3) as u8, (y & 0x7) as u8);
+
+		let (tile, attr) = self
+			.ppu
+			.fetch_bg_window_tile(tile_x, tile_y, offset_y, base);
+		let bitpos = if attr & 0x20 > 0 {
+			x & 0x7
+		} else {
+			7 - (x & 0x7)
+		};
+
+		(self.ppu.get_color_no(tile, bitpos as u8), attr)
+	}
+
+	/// Looks up a color in a BG or OBJ palette.
+	///
+	/// On DMG BG palettes are all BGP and OBJ palettes 0 and 1 are OBP0
+	/// and OBP1.
+	pub fn color(&self, obj: bool, palette: u8, color_no: u8) -> Color {
+		let ppu = self.ppu;
+
+		if ppu.cgb {
+			let ram = if obj {
+				&ppu.obj_palette_ram
+			} else {
+				&ppu.bg_palette_ram
+			};
+			return Color::Rgb555(PPU::cgb_color(ram, palette, color_no));
+		}
+
+		let palette = match (obj, palette & 1) {
+			(false, _) => ppu.bgp,
+			(true, 0) => ppu.obp0,
+			(true, _) => ppu.obp1,
+		};
+		Color::Shade(ppu.map_color(color_no, palette))
+	}
+
+	/// Height of sprites in pixels, 8 or 16.
+	pub fn sprite_height(&self) -> usize {
+		if self.ppu.lcdc & 0x4 > 0 {
+			16
+		} else {
+			8
+		}
+	}
+
+	/// Decodes one of the 40 OAM entries.
+	pub fn sprite(&self, i: usize) -> Sprite {
+		let entry = &self.ppu.oam[i << 2..][..4];
+
+		Sprite {
+			y: entry[0],
+			x: entry[1],
+			tile: entry[2],
+			flags: entry[3],
+		}
+	}
+}
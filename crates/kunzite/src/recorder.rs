@@ -0,0 +1,153 @@
+//! Recording gameplay to a video file
+//!
+//! Frames are handed over as packed RGB once per V-Blank. Y4M keeps every
+//! frame at the exact Game Boy refresh rate, GIF only has a resolution of
+//! 1/100s so every other frame is kept with delays averaging out to the
+//! right speed.
+
+use color_eyre::{eyre::eyre, Result};
+use std::{
+	fs::File,
+	io::{BufWriter, Write},
+	path::Path,
+};
+
+/// Refresh rate as a fraction, the 4 MiHz clock over 70224 cycles per frame
+const FPS_NUM: u64 = 4_194_304;
+const FPS_DEN: u64 = 70_224;
+
+/// Only every n-th frame ends up in a GIF
+const GIF_FRAME_STEP: u64 = 2;
+
+enum Sink {
+	Gif(gif::Encoder<BufWriter<File>>),
+	Y4m(BufWriter<File>),
+}
+
+/// Writes frames to an animated GIF or a Y4M stream
+pub struct Recorder {
+	sink: Sink,
+	width: usize,
+	height: usize,
+	/// Frames received so far
+	frames: u64,
+	/// Time written out to the GIF so far, in 1/100s
+	gif_time: u64,
+}
+
+impl Recorder {
+	/// Creates the file, the format is picked from the extension
+	pub fn create<P: AsRef<Path>>(path: P, (width, height): (usize, usize)) -> Result<Self> {
+		let path = path.as_ref();
+		let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+		let mut file = BufWriter::new(File::create(path)?);
+
+		let sink = match ext.to_ascii_lowercase().as_str() {
+			"gif" => {
+				let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[])?;
+				encoder.set_repeat(gif::Repeat::Infinite)?;
+				Sink::Gif(encoder)
+			}
+			"y4m" => {
+				// 4:4:4 so colours don't bleed between the tiny pixels
+				writeln!(
+					file,
+					"YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+					width, height, FPS_NUM, FPS_DEN
+				)?;
+				Sink::Y4m(file)
+			}
+			_ => return Err(eyre!("Unsupported video format: {}", path.display())),
+		};
+
+		Ok(Self {
+			sink,
+			width,
+			height,
+			frames: 0,
+			gif_time: 0,
+		})
+	}
+
+	/// Number of frames received so far
+	pub fn frames(&self) -> u64 {
+		self.frames
+	}
+
+	/// Adds a frame of packed RGB pixels
+	pub fn frame(&mut self, rgb: &[u8]) -> Result<()> {
+		let frame = self.frames;
+		self.frames += 1;
+
+		match &mut self.sink {
+			Sink::Gif(encoder) => {
+				if frame % GIF_FRAME_STEP > 0 {
+					return Ok(());
+				}
+
+				// Round the end of this frame to 1/100s and make up for the
+				// error on the next one
+				let end = (frame + GIF_FRAME_STEP) * FPS_DEN * 100 / FPS_NUM;
+				let delay = end - self.gif_time;
+				self.gif_time = end;
+
+				let mut frame =
+					gif::Frame::from_rgb_speed(self.width as u16, self.height as u16, rgb, 10);
+				frame.delay = delay as u16;
+				encoder.write_frame(&frame)?;
+			}
+			Sink::Y4m(file) => {
+				file.write_all(b"FRAME\n")?;
+				for plane in 0..3 {
+					let bytes: Vec<u8> = rgb.chunks(3).map(|px| yuv(px)[plane]).collect();
+					file.write_all(&bytes)?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Flushes everything to disk
+	pub fn finish(self) -> Result<()> {
+		let mut file = match self.sink {
+			// Writes the trailer, dropping the encoder would ignore errors
+			Sink::Gif(encoder) => encoder.into_inner()?,
+			Sink::Y4m(file) => file,
+		};
+		file.flush()?;
+
+		Ok(())
+	}
+}
+
+/// Converts a pixel to studio swing BT.601 Y'CbCr
+fn yuv(rgb: &[u8]) -> [u8; 3] {
+	let (r, g, b) = (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
+
+	let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+	let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+	let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+
+	[y as u8, u as u8, v as u8]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{env, fs};
+
+	#[test]
+	fn test_gif_trailer() {
+		let path = env::temp_dir().join("kunzite-recorder-test.gif");
+		let mut recorder = Recorder::create(&path, (2, 2)).unwrap();
+		for _ in 0..4 {
+			recorder.frame(&[0xFF; 12]).unwrap();
+		}
+		recorder.finish().unwrap();
+
+		let gif = fs::read(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(gif.last(), Some(&0x3B));
+	}
+}
@@ -0,0 +1,48 @@
+//! Saving frames as PNG
+
+use color_eyre::Result;
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// Scales packed RGB pixels up by an integer factor, nearest neighbour
+pub fn scale(rgb: &[u8], width: usize, height: usize, factor: usize) -> Vec<u8> {
+	if factor == 1 {
+		return rgb.to_vec();
+	}
+
+	let mut out = Vec::with_capacity(rgb.len() * factor * factor);
+	for y in 0..height {
+		let row = &rgb[y * width * 3..][..width * 3];
+
+		let mut scaled = Vec::with_capacity(row.len() * factor);
+		for pixel in row.chunks(3) {
+			for _ in 0..factor {
+				scaled.extend_from_slice(pixel);
+			}
+		}
+
+		for _ in 0..factor {
+			out.extend_from_slice(&scaled);
+		}
+	}
+
+	out
+}
+
+/// Encodes packed RGB pixels as a PNG, `factor` times the original size
+pub fn save_png<P: AsRef<Path>>(
+	path: P,
+	rgb: &[u8],
+	(width, height): (usize, usize),
+	factor: usize,
+) -> Result<()> {
+	let file = BufWriter::new(File::create(path)?);
+
+	let mut encoder = png::Encoder::new(file, (width * factor) as u32, (height * factor) as u32);
+	encoder.set_color(png::ColorType::RGB);
+	encoder.set_depth(png::BitDepth::Eight);
+
+	let mut writer = encoder.write_header()?;
+	writer.write_image_data(&scale(rgb, width, height, factor))?;
+
+	Ok(())
+}
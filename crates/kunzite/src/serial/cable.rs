@@ -0,0 +1,70 @@
+//! Link cable between two emulated Game Boys
+//!
+//! The side with the internal clock may clock several bits before the other
+//! side runs again, with the fast clock or when it is behind by an
+//! instruction. The pulses queue up on the wire, and each one gets the bit
+//! the other side will have shifted out by the time it takes the pulse.
+
+use super::SerialDevice;
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+/// State of the lines shared by both ends
+struct Wire {
+	/// Byte each side is shifting out, as of the last time it listened for
+	/// the clock
+	sb: [u8; 2],
+	/// Bits clocked into each side by the other one, waiting to be taken
+	pulses: [VecDeque<bool>; 2],
+}
+
+impl Default for Wire {
+	fn default() -> Self {
+		Self {
+			// A side that never listens reads like a disconnected line
+			sb: [0xFF; 2],
+			pulses: Default::default(),
+		}
+	}
+}
+/// One end of a link cable
+pub struct CablePort {
+	wire: Rc<RefCell<Wire>>,
+	side: usize,
+}
+
+/// Creates a cable, plug one end into each Game Boy
+pub fn link_cable() -> (CablePort, CablePort) {
+	let wire = Rc::new(RefCell::new(Wire::default()));
+
+	(
+		CablePort {
+			wire: wire.clone(),
+			side: 0,
+		},
+		CablePort { wire, side: 1 },
+	)
+}
+
+impl SerialDevice for CablePort {
+	fn exchange(&mut self, out: bool) -> bool {
+		let wire = &mut *self.wire.borrow_mut();
+		let pulses = &mut wire.pulses[1 - self.side];
+
+		// Nobody takes them if the other side isn't listening
+		if pulses.len() == 8 {
+			pulses.pop_front();
+		}
+		// The other side shifts once for each pulse still ahead of this one
+		let ahead = pulses.len();
+		pulses.push_back(out);
+
+		wire.sb[1 - self.side] << ahead & 0x80 > 0
+	}
+
+	fn external_clock(&mut self, sb: u8) -> Option<bool> {
+		let mut wire = self.wire.borrow_mut();
+
+		wire.sb[self.side] = sb;
+		wire.pulses[self.side].pop_front()
+	}
+}
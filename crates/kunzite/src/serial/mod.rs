@@ -0,0 +1,326 @@
+//! Serial port
+//!
+//! A transfer shifts SB out one bit at a time, most significant first, while
+//! the bits from the other end of the cable are shifted in. The side using
+//! the internal clock drives the transfer, the other one waits for its
+//! pulses. Whatever is plugged into the port is a [`SerialDevice`].
+
+mod cable;
+mod printer;
+mod tcp;
+
+pub use self::{
+	cable::{link_cable, CablePort},
+	printer::Printer,
+	tcp::TcpLink,
+};
+use color_eyre::{eyre::eyre, Report, Result};
+use std::{
+	io::{self, Write},
+	path::PathBuf,
+	str::FromStr,
+};
+
+/// T-cycles per bit with the internal clock, 8192 Hz
+const SLOW_PERIOD: u32 = 512;
+/// T-cycles per bit with the CGB fast clock, 262144 Hz
+const FAST_PERIOD: u32 = 16;
+
+/// T-cycles per bit with the internal clock, for the clock speed in `sc`
+fn bit_period(sc: u8) -> u32 {
+	if sc & 0x02 > 0 {
+		FAST_PERIOD
+	} else {
+		SLOW_PERIOD
+	}
+}
+
+/// The other end of the link cable
+pub trait SerialDevice {
+	/// Exchanges a bit clocked by the Game Boy, returns the bit received
+	fn exchange(&mut self, out: bool) -> bool;
+
+	/// Called when the Game Boy starts a transfer of `sb` with control `sc`,
+	/// for devices that deal in whole bytes
+	fn start_transfer(&mut self, _sb: u8, _sc: u8) {}
+
+	/// Lets the given number of cpu T-cycles pass
+	fn tick(&mut self, _cycles: u32) {}
+
+	/// Gives the device a chance to clock a bit while the Game Boy is set to
+	/// the external clock, `sb` is the byte it is shifting out, most
+	/// significant bit first. Returns the bit received if the device pulsed
+	/// the clock, and is called again until it returns `None`.
+	fn external_clock(&mut self, _sb: u8) -> Option<bool> {
+		None
+	}
+}
+
+/// Nothing plugged in, the input line is pulled high
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+	fn exchange(&mut self, _out: bool) -> bool {
+		true
+	}
+}
+
+/// The output wired back to the input
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+	fn exchange(&mut self, out: bool) -> bool {
+		out
+	}
+}
+
+/// Prints every byte sent, handy for test ROMs that report over serial
+#[derive(Default)]
+pub struct Stdout {
+	byte: u8,
+	bits: u8,
+}
+
+impl SerialDevice for Stdout {
+	fn exchange(&mut self, out: bool) -> bool {
+		self.byte = self.byte << 1 | out as u8;
+		self.bits += 1;
+
+		if self.bits == 8 {
+			let mut stdout = io::stdout();
+			// Nothing sensible to do if stdout is gone
+			let _ = stdout.write_all(&[self.byte]).and_then(|_| stdout.flush());
+			self.bits = 0;
+		}
+
+		true
+	}
+}
+
+/// Device picked on the command line
+#[derive(Clone, PartialEq, Debug)]
+pub enum Connection {
+	None,
+	Loopback,
+	Stdout,
+	/// Game Boy Printer saving to a directory
+	Printer(PathBuf),
+	/// Waits for another emulator to connect to `host:port`
+	Listen(String),
+	/// Connects to another emulator at `host:port`
+	Connect(String),
+}
+
+impl Connection {
+	/// Creates the device, network links are set up before this returns
+	pub fn device(&self) -> Result<Box<dyn SerialDevice>> {
+		Ok(match self {
+			Connection::None => Box::new(Disconnected),
+			Connection::Loopback => Box::new(Loopback),
+			Connection::Stdout => Box::new(Stdout::default()),
+			Connection::Printer(dir) => Box::new(Printer::new(dir)),
+			Connection::Listen(addr) => Box::new(TcpLink::listen(addr)?),
+			Connection::Connect(addr) => Box::new(TcpLink::connect(addr)?),
+		})
+	}
+}
+
+impl FromStr for Connection {
+	type Err = Report;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		match s {
+			"none" => Ok(Connection::None),
+			"loopback" => Ok(Connection::Loopback),
+			"stdout" => Ok(Connection::Stdout),
+			"printer" => Ok(Connection::Printer(PathBuf::from("."))),
+			_ => Err(eyre!("Unknown serial device {}", s)),
+		}
+	}
+}
+
+/// Serial controller
+pub struct Serial {
+	/// Serial transfer data [FF01]
+	sb: u8,
+	/// Serial control [FF02]
+	///
+	/// Bit 7 starts a transfer and stays set until it's done, bit 1 selects
+	/// the fast clock (CGB) and bit 0 the internal clock.
+	sc: u8,
+	/// Bits left in the current transfer
+	bits: u8,
+	/// T-cycles until the next bit with the internal clock
+	timer: u32,
+	cgb: bool,
+	device: Box<dyn SerialDevice>,
+	/// Serial interrupt request
+	pub irq: bool,
+}
+
+impl Serial {
+	pub fn new() -> Self {
+		Self {
+			sb: 0,
+			sc: 0,
+			bits: 0,
+			timer: 0,
+			cgb: false,
+			device: Box::new(Disconnected),
+			irq: false,
+		}
+	}
+
+	pub fn set_cgb(&mut self, cgb: bool) {
+		self.cgb = cgb;
+	}
+
+	/// Plugs a device into the port, returns the one that was there
+	pub fn connect(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+		std::mem::replace(&mut self.device, device)
+	}
+
+	pub fn read(&self, addr: usize) -> u8 {
+		match addr {
+			0xFF01 => self.sb,
+			// Unused bits read as 1, the clock speed only exists on the CGB
+			0xFF02 if self.cgb => self.sc | 0x7C,
+			0xFF02 => self.sc | 0x7E,
+			_ => unreachable!(),
+		}
+	}
+
+	pub fn write(&mut self, addr: usize, val: u8) {
+		match addr {
+			0xFF01 => self.sb = val,
+			0xFF02 => {
+				self.sc = val & if self.cgb { 0x83 } else { 0x81 };
+				if self.transferring() {
+					self.bits = 8;
+					self.timer = self.period();
+					self.device.start_transfer(self.sb, self.sc);
+				}
+			}
+			_ => unreachable!(),
+		}
+	}
+
+	fn transferring(&self) -> bool {
+		self.sc & 0x80 > 0
+	}
+
+	fn internal_clock(&self) -> bool {
+		self.sc & 0x01 > 0
+	}
+
+	fn period(&self) -> u32 {
+		bit_period(self.sc)
+	}
+
+	/// Shifts one bit out and one in
+	fn shift(&mut self, input: bool) {
+		self.sb = self.sb << 1 | input as u8;
+		self.bits -= 1;
+
+		if self.bits == 0 {
+			self.sc &= 0x7F;
+			self.irq = true;
+		}
+	}
+
+	/// Runs for the given number of cpu T-cycles
+	///
+	/// The clock is derived from the cpu clock, so it doubles in double speed
+	/// mode.
+	pub fn update(&mut self, cycles: u8) {
+		self.device.tick(cycles as u32);
+
+		if !self.internal_clock() {
+			while let Some(input) = self.device.external_clock(self.sb) {
+				// Pulses while no transfer is running are lost
+				if self.transferring() {
+					self.shift(input);
+				}
+			}
+			return;
+		}
+		if !self.transferring() {
+			return;
+		}
+
+		let mut cycles = cycles as u32;
+		while self.transferring() && cycles >= self.timer {
+			cycles -= self.timer;
+			self.timer = self.period();
+
+			let out = self.sb & 0x80 > 0;
+			let input = self.device.exchange(out);
+			self.shift(input);
+		}
+		if self.transferring() {
+			self.timer -= cycles;
+		}
+	}
+}
+
+impl Default for Serial {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Runs a transfer with the internal clock, returns the cycles it took
+	fn transfer(serial: &mut Serial, sc: u8) -> u32 {
+		serial.write(0xFF02, sc);
+
+		let mut cycles = 0;
+		while serial.read(0xFF02) & 0x80 > 0 {
+			serial.update(4);
+			cycles += 4;
+		}
+
+		cycles
+	}
+
+	#[test]
+	fn test_transfer() {
+		let mut serial = Serial::new();
+		serial.write(0xFF01, 0x5A);
+		assert_eq!(transfer(&mut serial, 0x81), 8 * SLOW_PERIOD);
+		// Nothing on the other end
+		assert_eq!(serial.read(0xFF01), 0xFF);
+		assert!(serial.irq);
+
+		serial.connect(Box::new(Loopback));
+		serial.write(0xFF01, 0x5A);
+		transfer(&mut serial, 0x81);
+		assert_eq!(serial.read(0xFF01), 0x5A);
+	}
+
+	#[test]
+	fn test_external_clock() {
+		let mut serial = Serial::new();
+		serial.write(0xFF02, 0x80);
+		for _ in 0..10_000 {
+			serial.update(4);
+		}
+
+		// Waits for a clock that never comes
+		assert_eq!(serial.read(0xFF02), 0xFE);
+		assert!(!serial.irq);
+	}
+
+	#[test]
+	fn test_fast_clock() {
+		let mut serial = Serial::new();
+		// Only the CGB has the fast clock
+		assert_eq!(transfer(&mut serial, 0x83), 8 * SLOW_PERIOD);
+
+		serial.set_cgb(true);
+		assert_eq!(transfer(&mut serial, 0x83), 8 * FAST_PERIOD);
+	}
+}
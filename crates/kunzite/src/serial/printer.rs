@@ -0,0 +1,449 @@
+//! Game Boy Printer
+//!
+//! Games talk to the printer in packets:
+//!
+//! | Bytes | Content                                       |
+//! |-------|-----------------------------------------------|
+//! | 2     | Magic 0x88 0x33                               |
+//! | 1     | Command                                       |
+//! | 1     | 1 if the data is compressed                   |
+//! | 2     | Length of the data                            |
+//! | n     | Data                                          |
+//! | 2     | Checksum, sum of command to the end of data   |
+//! | 2     | Printer answers 0x81, then its status         |
+//!
+//! Tile data arrives two rows of tiles at a time and is kept until a PRINT
+//! command puts it on paper. A job lasts until the paper is fed after an
+//! image, each one is saved as `print-N.png`.
+
+use super::SerialDevice;
+use crate::{audio::CLOCK_RATE, palette::Palette, screenshot};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// Width of the paper in pixels, 20 tiles
+const WIDTH: usize = 160;
+/// Size of a tile in bytes
+const TILE_LEN: usize = 16;
+/// Most image data the printer holds, 9 bands of 2 tile rows
+const BUFFER_LEN: usize = 9 * 0x280;
+/// Pixel rows of paper fed for each step of a margin
+const MARGIN_LINES: usize = 8;
+/// How long printing takes, the printer reports being busy meanwhile
+const PRINT_CYCLES: u64 = CLOCK_RATE as u64;
+/// Sent in the first byte after a packet
+const ALIVE: u8 = 0x81;
+
+/// Bits of the status byte
+const STATUS_CHECKSUM: u8 = 0x01;
+const STATUS_BUSY: u8 = 0x02;
+const STATUS_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+
+/// Where in a packet the next byte goes
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum State {
+	Magic1,
+	Magic2,
+	Command,
+	Compression,
+	LengthLow,
+	LengthHigh,
+	Data,
+	ChecksumLow,
+	ChecksumHigh,
+	Alive,
+	Status,
+}
+
+/// A packet being received
+#[derive(Default)]
+struct Packet {
+	command: u8,
+	/// Bit 0 is set if the data is compressed, the whole byte counts
+	/// towards the checksum
+	compression: u8,
+	length: u16,
+	data: Vec<u8>,
+	checksum: u16,
+}
+
+impl Packet {
+	fn compressed(&self) -> bool {
+		self.compression & 0x01 > 0
+	}
+
+	/// Sum of all bytes from the command up to the checksum
+	fn sum(&self) -> u16 {
+		let header = [
+			self.command,
+			self.compression,
+			self.length as u8,
+			(self.length >> 8) as u8,
+		];
+
+		header
+			.iter()
+			.chain(&self.data)
+			.fold(0u16, |sum, &b| sum.wrapping_add(b as u16))
+	}
+}
+
+/// Game Boy Printer, saves what it prints to a directory
+pub struct Printer {
+	dir: PathBuf,
+	state: State,
+	packet: Packet,
+	status: u8,
+	/// Decompressed tile data waiting to be printed
+	buffer: Vec<u8>,
+	/// Shades of the current job so far, [`WIDTH`] per row
+	paper: Vec<u8>,
+	/// File of the current job
+	job: Option<PathBuf>,
+	/// Cycles until printing is done
+	busy: u64,
+	/// Bits of the byte being received
+	byte_in: u8,
+	/// Bits of the byte being sent
+	byte_out: u8,
+	bits: u8,
+}
+
+impl Printer {
+	pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+		Self {
+			dir: dir.as_ref().to_path_buf(),
+			state: State::Magic1,
+			packet: Packet::default(),
+			status: 0,
+			buffer: Vec::new(),
+			paper: Vec::new(),
+			job: None,
+			busy: 0,
+			byte_in: 0,
+			byte_out: 0,
+			bits: 0,
+		}
+	}
+
+	/// Takes the next byte of a packet, returns the byte to send back with
+	/// the one after it
+	fn receive(&mut self, byte: u8) -> u8 {
+		let packet = &mut self.packet;
+
+		self.state = match self.state {
+			State::Magic1 if byte == 0x88 => State::Magic2,
+			State::Magic1 => State::Magic1,
+			State::Magic2 if byte == 0x33 => State::Command,
+			// Could be the start of the magic after all
+			State::Magic2 if byte == 0x88 => State::Magic2,
+			State::Magic2 => State::Magic1,
+			State::Command => {
+				*packet = Packet {
+					command: byte,
+					..Packet::default()
+				};
+				State::Compression
+			}
+			State::Compression => {
+				packet.compression = byte;
+				State::LengthLow
+			}
+			State::LengthLow => {
+				packet.length = byte as u16;
+				State::LengthHigh
+			}
+			State::LengthHigh => {
+				packet.length |= (byte as u16) << 8;
+				if packet.length > 0 {
+					State::Data
+				} else {
+					State::ChecksumLow
+				}
+			}
+			State::Data => {
+				packet.data.push(byte);
+				if packet.data.len() == packet.length as usize {
+					State::ChecksumLow
+				} else {
+					State::Data
+				}
+			}
+			State::ChecksumLow => {
+				packet.checksum = byte as u16;
+				State::ChecksumHigh
+			}
+			State::ChecksumHigh => {
+				packet.checksum |= (byte as u16) << 8;
+				self.run_packet();
+				return ALIVE;
+			}
+			State::Alive => {
+				self.state = State::Status;
+				return self.status;
+			}
+			State::Status => State::Magic1,
+		};
+
+		0x00
+	}
+
+	/// Runs the command of a complete packet
+	fn run_packet(&mut self) {
+		self.state = State::Alive;
+
+		let packet = std::mem::take(&mut self.packet);
+		if packet.sum() != packet.checksum {
+			self.status |= STATUS_CHECKSUM;
+			return;
+		}
+		self.status &= !STATUS_CHECKSUM;
+
+		match packet.command {
+			// INIT
+			0x01 => {
+				self.buffer.clear();
+				self.status = 0;
+			}
+			// PRINT
+			0x02 if packet.data.len() == 4 => self.print(&packet.data),
+			// DATA, an empty one ends the image
+			0x04 if packet.data.is_empty() => self.status |= STATUS_FULL,
+			0x04 => {
+				let data = if packet.compressed() {
+					decompress(&packet.data)
+				} else {
+					packet.data
+				};
+				let room = BUFFER_LEN - self.buffer.len();
+				self.buffer.extend(data.into_iter().take(room));
+				self.status |= STATUS_UNPROCESSED;
+			}
+			// BREAK, stops printing
+			0x08 => {
+				self.busy = 0;
+				self.status &= !STATUS_BUSY;
+			}
+			// STATUS just asks for the status byte
+			_ => {}
+		}
+	}
+
+	/// Puts the buffered image on paper
+	///
+	/// The arguments are the number of sheets, the margins before and after
+	/// in the high and low nibble, the palette and the exposure.
+	fn print(&mut self, args: &[u8]) {
+		let (before, after) = ((args[1] >> 4) as usize, (args[1] & 0xF) as usize);
+		// 0 stands for the usual palette
+		let palette = if args[2] == 0 { 0xE4 } else { args[2] };
+
+		let image = decode_tiles(&self.buffer, palette);
+		self.paper
+			.resize(self.paper.len() + before * MARGIN_LINES * WIDTH, 0);
+		self.paper.extend(image);
+		self.paper
+			.resize(self.paper.len() + after * MARGIN_LINES * WIDTH, 0);
+
+		// The image so far is saved right away, so nothing is lost if the
+		// game never feeds the paper
+		let dir = &self.dir;
+		let path = self.job.get_or_insert_with(|| free_path(dir)).clone();
+		let rgb = Palette::builtin()[0].to_rgb(&self.paper);
+		let size = (WIDTH, self.paper.len() / WIDTH);
+		let saved = fs::create_dir_all(dir)
+			.map_err(Into::into)
+			.and_then(|()| screenshot::save_png(&path, &rgb, size, 1));
+		match saved {
+			Ok(()) => println!("Printed to {}", path.display()),
+			Err(err) => eprintln!("Failed to print to {}: {}", path.display(), err),
+		}
+
+		// Feeding the paper out ends the job
+		if after > 0 {
+			self.paper.clear();
+			self.job = None;
+		}
+
+		self.buffer.clear();
+		self.status = (self.status & !(STATUS_FULL | STATUS_UNPROCESSED)) | STATUS_BUSY;
+		self.busy = PRINT_CYCLES;
+	}
+}
+
+impl SerialDevice for Printer {
+	fn exchange(&mut self, out: bool) -> bool {
+		let bit = self.byte_out & 0x80 > 0;
+		self.byte_out <<= 1;
+		self.byte_in = self.byte_in << 1 | out as u8;
+		self.bits += 1;
+
+		if self.bits == 8 {
+			self.byte_out = self.receive(self.byte_in);
+			self.bits = 0;
+		}
+
+		bit
+	}
+
+	fn tick(&mut self, cycles: u32) {
+		if self.busy > 0 {
+			self.busy = self.busy.saturating_sub(cycles as u64);
+			if self.busy == 0 {
+				self.status &= !STATUS_BUSY;
+			}
+		}
+	}
+}
+
+/// Expands run length encoded data
+///
+/// A control byte with bit 7 set repeats the next byte `(n & 0x7F) + 2`
+/// times, otherwise the next `n + 1` bytes are copied.
+fn decompress(data: &[u8]) -> Vec<u8> {
+	let mut out = Vec::new();
+	let mut bytes = data.iter();
+
+	while let Some(&control) = bytes.next() {
+		if control & 0x80 > 0 {
+			if let Some(&byte) = bytes.next() {
+				let len = (control & 0x7F) as usize + 2;
+				out.resize(out.len() + len, byte);
+			}
+		} else {
+			out.extend(bytes.by_ref().take(control as usize + 1));
+		}
+	}
+
+	out
+}
+
+/// Turns tile data into rows of shades, 20 tiles per row
+fn decode_tiles(data: &[u8], palette: u8) -> Vec<u8> {
+	let tiles_per_row = WIDTH / 8;
+	let rows = data.len() / TILE_LEN / tiles_per_row;
+	let mut shades = vec![0; rows * 8 * WIDTH];
+
+	for (i, tile) in data
+		.chunks_exact(TILE_LEN)
+		.take(rows * tiles_per_row)
+		.enumerate()
+	{
+		let (tx, ty) = (i % tiles_per_row, i / tiles_per_row);
+		for y in 0..8 {
+			let (lo, hi) = (tile[y * 2], tile[y * 2 + 1]);
+			for x in 0..8 {
+				let color = (lo >> (7 - x) & 1) | (hi >> (7 - x) & 1) << 1;
+				let shade = palette >> (color * 2) & 0x3;
+				shades[(ty * 8 + y) * WIDTH + tx * 8 + x] = shade;
+			}
+		}
+	}
+
+	shades
+}
+
+/// First `print-N.png` in `dir` that doesn't exist yet
+fn free_path(dir: &Path) -> PathBuf {
+	(0..)
+		.map(|n| dir.join(format!("print-{}.png", n)))
+		.find(|path| !path.exists())
+		.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Sends a packet, returns the two bytes the printer answers with
+	fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+		let mut packet = Packet {
+			command,
+			compression: compressed as u8,
+			length: data.len() as u16,
+			data: data.to_vec(),
+			checksum: 0,
+		};
+		packet.checksum = packet.sum();
+
+		let mut bytes = vec![0x88, 0x33, command, compressed as u8];
+		bytes.extend_from_slice(&packet.length.to_le_bytes());
+		bytes.extend_from_slice(data);
+		bytes.extend_from_slice(&packet.checksum.to_le_bytes());
+		bytes.extend_from_slice(&[0, 0]);
+
+		let answers: Vec<u8> = bytes.iter().map(|&b| exchange_byte(printer, b)).collect();
+		(answers[answers.len() - 2], answers[answers.len() - 1])
+	}
+
+	fn exchange_byte(printer: &mut Printer, byte: u8) -> u8 {
+		(0..8).fold(0, |answer, bit| {
+			let out = byte << bit & 0x80 > 0;
+			answer << 1 | printer.exchange(out) as u8
+		})
+	}
+
+	#[test]
+	fn test_decompress() {
+		assert_eq!(
+			decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34]),
+			[0xAA, 0xAA, 0xAA, 0x12, 0x34]
+		);
+	}
+
+	#[test]
+	fn test_print() {
+		// The directory is created when the first image is printed
+		let dir = std::env::temp_dir().join("kunzite-printer-test");
+		let _ = std::fs::remove_dir_all(&dir);
+		let mut printer = Printer::new(&dir);
+
+		assert_eq!(send(&mut printer, 0x01, false, &[]), (ALIVE, 0x00));
+
+		// Two rows of black tiles, 640 bytes in runs of at most 129
+		let mut data = [0xFF, 0xFF].repeat(4);
+		data.extend_from_slice(&[0x80 | (124 - 2), 0xFF]);
+		assert_eq!(
+			send(&mut printer, 0x04, true, &data),
+			(ALIVE, STATUS_UNPROCESSED)
+		);
+		assert_eq!(
+			send(&mut printer, 0x04, false, &[]).1,
+			STATUS_FULL | STATUS_UNPROCESSED
+		);
+
+		// A broken checksum is reported and the packet ignored
+		let bytes = [0x88, 0x33, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+		let answers: Vec<u8> = bytes
+			.iter()
+			.map(|&b| exchange_byte(&mut printer, b))
+			.collect();
+		assert_eq!(answers[9] & STATUS_CHECKSUM, STATUS_CHECKSUM);
+
+		// A stray 0x88 before the magic, and unused compression bits that
+		// still count towards the checksum
+		let bytes = [
+			0x88, 0x88, 0x33, 0x0F, 0x02, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00,
+		];
+		let answers: Vec<u8> = bytes
+			.iter()
+			.map(|&b| exchange_byte(&mut printer, b))
+			.collect();
+		assert_eq!(answers[9], ALIVE);
+		assert_eq!(answers[10] & STATUS_CHECKSUM, 0);
+
+		// One sheet, a margin of 1 before and 3 after
+		let (_, status) = send(&mut printer, 0x02, false, &[0x01, 0x13, 0xE4, 0x40]);
+		assert_eq!(status, STATUS_BUSY);
+		let decoder = png::Decoder::new(std::fs::File::open(dir.join("print-0.png")).unwrap());
+		let (info, _) = decoder.read_info().unwrap();
+		assert_eq!((info.width, info.height), (160, 8 + 16 + 3 * 8));
+
+		printer.tick(PRINT_CYCLES as u32);
+		assert_eq!(send(&mut printer, 0x0F, false, &[]).1, 0x00);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
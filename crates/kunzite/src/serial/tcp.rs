@@ -0,0 +1,365 @@
+//! Link cable to another emulator over TCP
+//!
+//! Waiting a round trip for every bit would make transfers crawl, so whole
+//! bytes are exchanged instead. The side with the internal clock sends its
+//! byte when the transfer starts and waits for the other side's byte, which
+//! it then shifts in at the normal rate.
+//!
+//! Every message carries the time of the sender in cpu T-cycles since the
+//! link was made. The other side answers a transfer once it reaches that
+//! time, with its byte if it was waiting for one, and shifts in the bits at
+//! the times they were clocked. Neither side runs more than [`MAX_LEAD`]
+//! ahead of the other, so only a side that is already ahead answers late.
+//! Latency below that costs nothing, beyond it the faster side waits.
+//!
+//! The waiting happens on the emulation thread, so a side that hears nothing
+//! for [`TIMEOUT`], because the other emulator was paused or closed, drops
+//! the link rather than hang.
+
+use super::{bit_period, SerialDevice};
+use crate::gb::FRAME_CYCLES;
+use color_eyre::{eyre::eyre, Result};
+use std::{
+	io::{Read, Write},
+	net::{TcpListener, TcpStream},
+	sync::mpsc::{self, Receiver, RecvTimeoutError},
+	thread,
+	time::Duration,
+};
+
+/// Sent by both sides when connecting
+const MAGIC: &[u8; 4] = b"KZLK";
+/// Bumped whenever the messages change
+const PROTOCOL_VERSION: u16 = 1;
+/// Report the time at least this often
+const SYNC_PERIOD: u64 = 4096;
+/// How far one side may run ahead of the other
+const MAX_LEAD: u64 = FRAME_CYCLES as u64;
+/// Size of a message on the wire
+const MESSAGE_LEN: usize = 11;
+/// How long to wait for the other side before giving up on it, it reports
+/// many times a second while running
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What happened on one side
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Event {
+	/// A transfer of the byte started with the internal clock, with the
+	/// control register for the clock speed
+	Transfer(u8, u8),
+	/// Answer to a transfer with the byte of the other side
+	Reply(u8),
+	/// Nothing, just the time
+	Sync,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Message {
+	event: Event,
+	/// Time of the sender
+	time: u64,
+}
+
+impl Message {
+	fn encode(&self) -> [u8; MESSAGE_LEN] {
+		let (kind, a, b) = match self.event {
+			Event::Transfer(sb, sc) => (0, sb, sc),
+			Event::Reply(sb) => (1, sb, 0),
+			Event::Sync => (2, 0, 0),
+		};
+
+		let mut bytes = [0; MESSAGE_LEN];
+		bytes[0..3].copy_from_slice(&[kind, a, b]);
+		bytes[3..].copy_from_slice(&self.time.to_le_bytes());
+		bytes
+	}
+
+	fn decode(bytes: &[u8; MESSAGE_LEN]) -> Result<Self> {
+		let event = match bytes[0] {
+			0 => Event::Transfer(bytes[1], bytes[2]),
+			1 => Event::Reply(bytes[1]),
+			2 => Event::Sync,
+			kind => return Err(eyre!("Unknown link message {}", kind)),
+		};
+		let mut time = [0; 8];
+		time.copy_from_slice(&bytes[3..]);
+
+		Ok(Self {
+			event,
+			time: u64::from_le_bytes(time),
+		})
+	}
+}
+
+/// Bits of a byte received from the other side, waiting to be shifted in
+struct Incoming {
+	byte: u8,
+	bits: u8,
+	/// When the next bit is due, only used with the external clock
+	due: u64,
+	period: u64,
+}
+
+impl Incoming {
+	fn new(byte: u8, start: u64, period: u64) -> Self {
+		Self {
+			byte,
+			bits: 8,
+			due: start + period,
+			period,
+		}
+	}
+
+	fn next_bit(&mut self) -> bool {
+		let bit = self.byte & 0x80 > 0;
+		self.byte <<= 1;
+		self.bits -= 1;
+		self.due += self.period;
+		bit
+	}
+}
+
+/// One end of a link cable to another emulator
+pub struct TcpLink {
+	stream: TcpStream,
+	/// Messages from the reader thread, closed once the other side is gone
+	messages: Receiver<Message>,
+	connected: bool,
+	/// Cpu T-cycles since the link was made
+	time: u64,
+	/// Last time the other side reported
+	remote_time: u64,
+	/// Last time we reported
+	sent_time: u64,
+	/// Byte waiting for the other side's clock
+	armed: Option<u8>,
+	/// Transfer started by the other side, with its time
+	transfer: Option<(u8, u8, u64)>,
+	/// Answer to our last transfer
+	reply: Option<u8>,
+	incoming: Option<Incoming>,
+}
+
+impl TcpLink {
+	/// Waits for another emulator to connect to `addr`, `host:port` of the
+	/// interface to listen on
+	pub fn listen(addr: &str) -> Result<Self> {
+		let listener = TcpListener::bind(addr)?;
+		println!("Waiting for the link on {}", addr);
+		let (stream, addr) = listener.accept()?;
+		println!("Linked with {}", addr);
+
+		Self::new(stream)
+	}
+
+	/// Connects to an emulator listening at `addr`
+	pub fn connect(addr: &str) -> Result<Self> {
+		let stream = TcpStream::connect(addr)?;
+		println!("Linked with {}", addr);
+
+		Self::new(stream)
+	}
+
+	/// Checks both sides speak the same protocol and starts reading
+	fn new(mut stream: TcpStream) -> Result<Self> {
+		stream.set_nodelay(true)?;
+		stream.write_all(MAGIC)?;
+		stream.write_all(&PROTOCOL_VERSION.to_le_bytes())?;
+
+		let mut hello = [0; 6];
+		stream.read_exact(&mut hello)?;
+		if &hello[0..4] != MAGIC {
+			return Err(eyre!("The other side is not a link cable"));
+		}
+		let version = u16::from_le_bytes([hello[4], hello[5]]);
+		if version != PROTOCOL_VERSION {
+			return Err(eyre!(
+				"Link protocol version {} on the other side, expected {}",
+				version,
+				PROTOCOL_VERSION
+			));
+		}
+
+		let (sender, messages) = mpsc::channel();
+		let mut reader = stream.try_clone()?;
+		thread::spawn(move || {
+			let mut bytes = [0; MESSAGE_LEN];
+			while reader.read_exact(&mut bytes).is_ok() {
+				match Message::decode(&bytes) {
+					Ok(message) if sender.send(message).is_ok() => {}
+					_ => break,
+				}
+			}
+		});
+
+		Ok(Self {
+			stream,
+			messages,
+			connected: true,
+			time: 0,
+			remote_time: 0,
+			sent_time: 0,
+			armed: None,
+			transfer: None,
+			reply: None,
+			incoming: None,
+		})
+	}
+
+	fn send(&mut self, event: Event) {
+		let message = Message {
+			event,
+			time: self.time,
+		};
+		if self.stream.write_all(&message.encode()).is_err() {
+			self.disconnect("the connection broke");
+		}
+		self.sent_time = self.time;
+	}
+
+	fn disconnect(&mut self, reason: &str) {
+		if self.connected {
+			eprintln!("Link cable disconnected, {}", reason);
+			self.connected = false;
+		}
+	}
+
+	/// Handles the messages that already arrived
+	fn poll(&mut self) {
+		while let Ok(message) = self.messages.try_recv() {
+			self.handle(message);
+		}
+	}
+
+	/// Waits for the next message, up to [`TIMEOUT`]
+	fn receive(&mut self) {
+		match self.messages.recv_timeout(TIMEOUT) {
+			Ok(message) => self.handle(message),
+			Err(RecvTimeoutError::Timeout) => {
+				self.disconnect("the other side stopped responding, was it paused?")
+			}
+			Err(RecvTimeoutError::Disconnected) => self.disconnect("the other side closed it"),
+		}
+	}
+
+	fn handle(&mut self, message: Message) {
+		self.remote_time = self.remote_time.max(message.time);
+
+		match message.event {
+			Event::Transfer(sb, sc) => self.transfer = Some((sb, sc, message.time)),
+			Event::Reply(sb) => self.reply = Some(sb),
+			Event::Sync => {}
+		}
+	}
+
+	/// Answers a transfer of the other side once its time has come, or
+	/// right away if `now`
+	fn answer(&mut self, now: bool) {
+		let (sb, sc, time) = match self.transfer {
+			Some(transfer) if now || transfer.2 <= self.time => transfer,
+			_ => return,
+		};
+		self.transfer = None;
+
+		// Without a transfer waiting the line just reads high
+		let armed = self.armed.take();
+		self.send(Event::Reply(armed.unwrap_or(0xFF)));
+		if armed.is_some() {
+			self.incoming = Some(Incoming::new(sb, time, bit_period(sc) as u64));
+		}
+	}
+}
+
+impl SerialDevice for TcpLink {
+	fn start_transfer(&mut self, sb: u8, sc: u8) {
+		if sc & 0x01 == 0 {
+			self.armed = Some(sb);
+			return;
+		}
+
+		self.reply = None;
+		self.send(Event::Transfer(sb, sc));
+		while self.reply.is_none() && self.connected {
+			self.receive();
+			// The other side may be waiting on us just the same
+			self.answer(true);
+		}
+
+		let reply = self.reply.take().unwrap_or(0xFF);
+		self.incoming = Some(Incoming::new(reply, self.time, 0));
+	}
+
+	fn exchange(&mut self, _out: bool) -> bool {
+		match &mut self.incoming {
+			Some(incoming) if incoming.bits > 0 => incoming.next_bit(),
+			_ => true,
+		}
+	}
+
+	fn external_clock(&mut self, _sb: u8) -> Option<bool> {
+		match &mut self.incoming {
+			Some(incoming) if incoming.bits > 0 && incoming.due <= self.time => {
+				Some(incoming.next_bit())
+			}
+			_ => None,
+		}
+	}
+
+	fn tick(&mut self, cycles: u32) {
+		self.time += cycles as u64;
+		if !self.connected {
+			return;
+		}
+
+		self.poll();
+		self.answer(false);
+		if self.time - self.sent_time >= SYNC_PERIOD {
+			self.send(Event::Sync);
+		}
+		while self.connected && self.time > self.remote_time + MAX_LEAD {
+			self.receive();
+			self.answer(false);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::serial::Serial;
+
+	/// Runs a transfer over a link, returns SB afterwards
+	fn transfer(link: TcpLink, sb: u8, sc: u8) -> u8 {
+		let mut serial = Serial::new();
+		serial.connect(Box::new(link));
+		serial.write(0xFF01, sb);
+		serial.write(0xFF02, sc);
+		while serial.read(0xFF02) & 0x80 > 0 {
+			serial.update(4);
+		}
+
+		serial.read(0xFF01)
+	}
+
+	#[test]
+	fn test_message() {
+		let message = Message {
+			event: Event::Transfer(0x42, 0x81),
+			time: 123_456,
+		};
+		assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+	}
+
+	#[test]
+	fn test_transfer() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap().to_string();
+
+		let slave = thread::spawn(move || transfer(TcpLink::connect(&addr).unwrap(), 0x99, 0x80));
+		let (stream, _) = listener.accept().unwrap();
+		let master = transfer(TcpLink::new(stream).unwrap(), 0x42, 0x81);
+
+		assert_eq!(master, 0x99);
+		assert_eq!(slave.join().unwrap(), 0x42);
+	}
+}
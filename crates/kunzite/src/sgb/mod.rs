@@ -0,0 +1,391 @@
+//! Super Game Boy
+//!
+//! Games talk to the SNES side by pulsing the P14/P15 lines of P1, and
+//! larger payloads (palettes, border graphics) are sent by putting them on
+//! screen and asking the SGB to capture the next frame.
+
+mod packet;
+mod render;
+
+use self::packet::{PacketReader, PACKET_LEN};
+pub use self::render::{SGB_H, SGB_W};
+use std::cmp::Ordering;
+
+/// Width of the game screen in 8x8 attribute cells
+const CELLS_W: usize = 20;
+/// Height of the game screen in 8x8 attribute cells
+const CELLS_H: usize = 18;
+/// Bytes captured from the screen by a VRAM transfer
+const TRANSFER_LEN: usize = 0x1000;
+/// Size of an attribute file, 4 cells per byte
+const ATTR_FILE_LEN: usize = CELLS_W * CELLS_H / 4;
+/// Number of attribute files sent by ATTR_TRN
+const ATTR_FILES: usize = 45;
+
+/// What is shown in place of the game screen
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Mask {
+	None,
+	/// Keep showing the last frame
+	Freeze,
+	/// Black screen
+	Black,
+	/// Fill with color 0
+	Color0,
+}
+
+/// A pending VRAM transfer
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Transfer {
+	/// PAL_TRN, 512 system palettes
+	Palettes,
+	/// ATTR_TRN, 45 attribute files
+	Attributes,
+	/// CHR_TRN, half of the border tiles
+	Tiles(usize),
+	/// PCT_TRN, border tile map and palettes
+	Border,
+}
+
+/// Super Game Boy
+pub struct Sgb {
+	reader: PacketReader,
+	/// Packets of the command being received
+	command: Vec<u8>,
+	/// Game screen palettes 0-3, color 0 is shared by all of them
+	palettes: [[u16; 4]; 4],
+	/// System palettes loaded with PAL_TRN
+	system_palettes: Vec<[u16; 4]>,
+	/// Attribute files loaded with ATTR_TRN
+	attr_files: Vec<u8>,
+	/// Palette of every 8x8 cell of the game screen
+	attr_map: [u8; CELLS_W * CELLS_H],
+	/// 256 border tiles in SNES 4bpp format
+	border_tiles: Vec<u8>,
+	/// Border tile map, 32x28 entries
+	border_map: Vec<u16>,
+	/// Border palettes 4-7
+	border_palettes: [[u16; 16]; 4],
+	mask: Mask,
+	/// Last frame before the screen was frozen
+	frozen: Vec<u8>,
+	/// VRAM transfer waiting for the next frame
+	transfer: Option<Transfer>,
+	/// Number of joypads requested with MLT_REQ
+	players: usize,
+}
+
+impl Sgb {
+	pub fn new() -> Self {
+		// Palette 1-A, what the SGB BIOS shows by default
+		const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+		Self {
+			reader: PacketReader::new(),
+			command: Vec::new(),
+			palettes: [DEFAULT_PALETTE; 4],
+			system_palettes: vec![[0; 4]; 512],
+			attr_files: vec![0; ATTR_FILE_LEN * ATTR_FILES],
+			attr_map: [0; CELLS_W * CELLS_H],
+			border_tiles: vec![0; 256 * 32],
+			border_map: vec![0; 32 * 28],
+			border_palettes: [[0; 16]; 4],
+			mask: Mask::None,
+			frozen: vec![0; 160 * 144],
+			transfer: None,
+			players: 1,
+		}
+	}
+
+	/// Watches writes to P1 for command packets
+	pub fn write_p1(&mut self, val: u8) {
+		let packet = match self.reader.write(val) {
+			Some(packet) => packet,
+			None => return,
+		};
+
+		self.command.extend_from_slice(&packet);
+
+		// The low 3 bits of the first byte hold the number of packets
+		let packets = ((self.command[0] & 0x7) as usize).max(1);
+		if self.command.len() >= packets * PACKET_LEN {
+			let command = std::mem::take(&mut self.command);
+			self.run_command(&command);
+		}
+	}
+
+	/// Number of joypads the game asked for with MLT_REQ
+	pub fn players(&self) -> usize {
+		self.players
+	}
+
+	/// Called at the start of V-Blank with the frame that was just drawn
+	pub fn vblank(&mut self, frame: &[u8]) {
+		if self.mask != Mask::Freeze {
+			self.frozen.copy_from_slice(frame);
+		}
+
+		if let Some(transfer) = self.transfer.take() {
+			let data = Self::capture(frame);
+			self.finish_transfer(transfer, &data);
+		}
+	}
+
+	fn run_command(&mut self, data: &[u8]) {
+		match data[0] >> 3 {
+			0x00 => self.set_palettes(0, 1, data),
+			0x01 => self.set_palettes(2, 3, data),
+			0x02 => self.set_palettes(0, 3, data),
+			0x03 => self.set_palettes(1, 2, data),
+			0x04 => self.attr_blk(data),
+			0x05 => self.attr_lin(data),
+			0x06 => self.attr_div(data),
+			0x07 => self.attr_chr(data),
+			0x0A => self.pal_set(data),
+			0x11 => {
+				self.players = match data[1] & 0x3 {
+					1 => 2,
+					3 => 4,
+					_ => 1,
+				}
+			}
+			0x0B => self.transfer = Some(Transfer::Palettes),
+			0x13 => self.transfer = Some(Transfer::Tiles((data[1] & 0x1) as usize)),
+			0x14 => self.transfer = Some(Transfer::Border),
+			0x15 => self.transfer = Some(Transfer::Attributes),
+			0x16 => self.attr_set(data[1]),
+			0x17 => {
+				self.mask = match data[1] & 0x3 {
+					0 => Mask::None,
+					1 => Mask::Freeze,
+					2 => Mask::Black,
+					_ => Mask::Color0,
+				}
+			}
+			// Sound, SNES code and the rest are of no use to us
+			_ => {}
+		}
+	}
+
+	/// PAL01, PAL23, PAL03 and PAL12
+	fn set_palettes(&mut self, a: usize, b: usize, data: &[u8]) {
+		let color = |i: usize| read_color(&data[1..], i);
+
+		for palette in self.palettes.iter_mut() {
+			palette[0] = color(0);
+		}
+		for i in 1..4 {
+			self.palettes[a][i] = color(i);
+			self.palettes[b][i] = color(i + 3);
+		}
+	}
+
+	/// PAL_SET, picks palettes from the ones sent with PAL_TRN
+	fn pal_set(&mut self, data: &[u8]) {
+		for i in 0..4 {
+			let id = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x1FF;
+			self.palettes[i] = self.system_palettes[id as usize];
+		}
+
+		let color0 = self.palettes[0][0];
+		for palette in self.palettes.iter_mut() {
+			palette[0] = color0;
+		}
+
+		if data[9] & 0x80 > 0 {
+			self.apply_attr_file(data[9] & 0x3F);
+		}
+		if data[9] & 0x40 > 0 {
+			self.mask = Mask::None;
+		}
+	}
+
+	/// ATTR_BLK, colours rectangular areas
+	fn attr_blk(&mut self, data: &[u8]) {
+		let count = data[1] as usize;
+
+		for set in data[2..].chunks_exact(6).take(count) {
+			let control = set[0] & 0x7;
+			let inside = set[1] & 0x3;
+			let border = set[1] >> 2 & 0x3;
+			let outside = set[1] >> 4 & 0x3;
+			let (x1, y1) = (set[2] as usize, set[3] as usize);
+			let (x2, y2) = (set[4] as usize, set[5] as usize);
+
+			// A lone inside or outside setting also applies to the border
+			let border = match control {
+				0x1 => Some(inside),
+				0x4 => Some(outside),
+				_ if control & 0x2 > 0 => Some(border),
+				_ => None,
+			};
+
+			for y in 0..CELLS_H {
+				for x in 0..CELLS_W {
+					let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+					let edge = x == x1 || x == x2 || y == y1 || y == y2;
+
+					let palette = match (within, edge) {
+						(true, false) if control & 0x1 > 0 => Some(inside),
+						(true, true) => border,
+						(false, _) if control & 0x4 > 0 => Some(outside),
+						_ => None,
+					};
+
+					if let Some(palette) = palette {
+						self.attr_map[y * CELLS_W + x] = palette;
+					}
+				}
+			}
+		}
+	}
+
+	/// ATTR_LIN, colours whole rows or columns
+	fn attr_lin(&mut self, data: &[u8]) {
+		let count = data[1] as usize;
+
+		for &line in data[2..].iter().take(count) {
+			let no = (line & 0x1F) as usize;
+			let palette = line >> 5 & 0x3;
+
+			if line & 0x80 > 0 {
+				if no < CELLS_H {
+					self.attr_map[no * CELLS_W..][..CELLS_W].fill(palette);
+				}
+			} else if no < CELLS_W {
+				for y in 0..CELLS_H {
+					self.attr_map[y * CELLS_W + no] = palette;
+				}
+			}
+		}
+	}
+
+	/// ATTR_DIV, splits the screen in two along a line
+	fn attr_div(&mut self, data: &[u8]) {
+		let after = data[1] & 0x3;
+		let before = data[1] >> 2 & 0x3;
+		let on_line = data[1] >> 4 & 0x3;
+		let horizontal = data[1] & 0x40 > 0;
+		let line = data[2] as usize;
+
+		for y in 0..CELLS_H {
+			for x in 0..CELLS_W {
+				let pos = if horizontal { y } else { x };
+				self.attr_map[y * CELLS_W + x] = match pos.cmp(&line) {
+					Ordering::Less => before,
+					Ordering::Equal => on_line,
+					Ordering::Greater => after,
+				};
+			}
+		}
+	}
+
+	/// ATTR_CHR, colours individual cells
+	fn attr_chr(&mut self, data: &[u8]) {
+		let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+		let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+		let vertical = data[5] & 0x1 > 0;
+
+		for i in 0..count.min(CELLS_W * CELLS_H) {
+			let byte = match data.get(6 + i / 4) {
+				Some(byte) => *byte,
+				None => break,
+			};
+
+			if x < CELLS_W && y < CELLS_H {
+				self.attr_map[y * CELLS_W + x] = byte >> (6 - (i % 4) * 2) & 0x3;
+			}
+
+			if vertical {
+				y += 1;
+				if y == CELLS_H {
+					y = 0;
+					x += 1;
+				}
+			} else {
+				x += 1;
+				if x == CELLS_W {
+					x = 0;
+					y += 1;
+				}
+			}
+		}
+	}
+
+	/// ATTR_SET, applies an attribute file sent with ATTR_TRN
+	fn attr_set(&mut self, val: u8) {
+		self.apply_attr_file(val & 0x3F);
+
+		if val & 0x40 > 0 {
+			self.mask = Mask::None;
+		}
+	}
+
+	fn apply_attr_file(&mut self, file: u8) {
+		let file = file as usize;
+		if file >= ATTR_FILES {
+			return;
+		}
+
+		let data = &self.attr_files[file * ATTR_FILE_LEN..][..ATTR_FILE_LEN];
+		for (i, cell) in self.attr_map.iter_mut().enumerate() {
+			*cell = data[i / 4] >> (6 - (i % 4) * 2) & 0x3;
+		}
+	}
+
+	/// Reads back the 4KB a game put on screen for a VRAM transfer
+	///
+	/// The SGB sees the screen as 2bpp tiles, 20 to a row.
+	fn capture(frame: &[u8]) -> Vec<u8> {
+		let mut data = vec![0; TRANSFER_LEN];
+
+		for (tile, bytes) in data.chunks_exact_mut(16).enumerate() {
+			let tile_x = (tile % CELLS_W) * 8;
+			let tile_y = (tile / CELLS_W) * 8;
+
+			for row in 0..8 {
+				let line = &frame[(tile_y + row) * 160 + tile_x..][..8];
+				for (bit, &shade) in line.iter().enumerate() {
+					bytes[row * 2] |= (shade & 0x1) << (7 - bit);
+					bytes[row * 2 + 1] |= (shade >> 1 & 0x1) << (7 - bit);
+				}
+			}
+		}
+
+		data
+	}
+
+	fn finish_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+		match transfer {
+			Transfer::Palettes => {
+				for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+					for (c, color) in palette.iter_mut().enumerate() {
+						*color = read_color(&data[i * 8..], c);
+					}
+				}
+			}
+			Transfer::Attributes => {
+				self.attr_files
+					.copy_from_slice(&data[..ATTR_FILE_LEN * ATTR_FILES]);
+			}
+			Transfer::Tiles(half) => {
+				self.border_tiles[half * TRANSFER_LEN..][..TRANSFER_LEN].copy_from_slice(data);
+			}
+			Transfer::Border => {
+				for (i, entry) in self.border_map.iter_mut().enumerate() {
+					*entry = u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+				}
+				for (i, palette) in self.border_palettes.iter_mut().enumerate() {
+					for (c, color) in palette.iter_mut().enumerate() {
+						*color = read_color(&data[0x800 + i * 32..], c);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Reads the `i`th little endian 15-bit color from a slice
+fn read_color(data: &[u8], i: usize) -> u16 {
+	u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]) & 0x7FFF
+}
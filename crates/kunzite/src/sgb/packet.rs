@@ -0,0 +1,103 @@
+/// Length of a single SGB packet in bytes
+pub const PACKET_LEN: usize = 16;
+
+/// Reassembles SGB packets from writes to P1
+///
+/// A packet starts with a reset pulse (P14 and P15 both low), followed by
+/// 128 bits sent LSB first where P14 low is a 0 and P15 low is a 1, each
+/// separated by both lines going high again. A final 0 bit ends the packet.
+#[derive(Default)]
+pub struct PacketReader {
+	/// Bits received so far, `None` when no packet is in progress
+	bits: Option<usize>,
+	/// Both lines went high since the last bit
+	ready: bool,
+	data: [u8; PACKET_LEN],
+}
+
+impl PacketReader {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Feeds a write to P1, returns a packet once it is complete
+	pub fn write(&mut self, val: u8) -> Option<[u8; PACKET_LEN]> {
+		let bit = match val & 0x30 {
+			0x00 => {
+				// Reset pulse
+				self.bits = Some(0);
+				self.ready = false;
+				self.data = [0; PACKET_LEN];
+				return None;
+			}
+			0x30 => {
+				self.ready = true;
+				return None;
+			}
+			0x20 => 0,
+			_ => 1,
+		};
+
+		let bits = self.bits?;
+		if !self.ready {
+			return None;
+		}
+		self.ready = false;
+
+		if bits == PACKET_LEN * 8 {
+			// Stop bit, has to be a 0
+			self.bits = None;
+			return if bit == 0 { Some(self.data) } else { None };
+		}
+
+		self.data[bits / 8] |= bit << (bits % 8);
+		self.bits = Some(bits + 1);
+
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Writes a packet to P1 the way the SGB BIOS documentation describes
+	fn send(reader: &mut PacketReader, data: &[u8; PACKET_LEN], stop: u8) -> Option<[u8; 16]> {
+		reader.write(0x00);
+		reader.write(0x30);
+
+		for i in 0..PACKET_LEN * 8 {
+			let bit = data[i / 8] >> (i % 8) & 1;
+			assert_eq!(reader.write(if bit == 1 { 0x10 } else { 0x20 }), None);
+			reader.write(0x30);
+		}
+
+		let packet = reader.write(if stop == 1 { 0x10 } else { 0x20 });
+		reader.write(0x30);
+		packet
+	}
+
+	#[test]
+	fn test_packet() {
+		let mut reader = PacketReader::new();
+		let mut data = [0; PACKET_LEN];
+		data[0] = 0x89;
+		data[1] = 0x01;
+		data[15] = 0xA5;
+
+		assert_eq!(send(&mut reader, &data, 0), Some(data));
+		// a packet with a bad stop bit is dropped
+		assert_eq!(send(&mut reader, &data, 1), None);
+	}
+
+	#[test]
+	fn test_ignores_joypad_polling() {
+		let mut reader = PacketReader::new();
+
+		for _ in 0..200 {
+			assert_eq!(reader.write(0x20), None);
+			assert_eq!(reader.write(0x10), None);
+			assert_eq!(reader.write(0x30), None);
+		}
+	}
+}
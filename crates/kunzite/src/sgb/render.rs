@@ -0,0 +1,80 @@
+use super::{Mask, Sgb, CELLS_W};
+use crate::palette::rgb555;
+
+/// Width of the SGB picture in pixels, border included
+pub const SGB_W: usize = 256;
+/// Height of the SGB picture in pixels, border included
+pub const SGB_H: usize = 224;
+
+/// Position of the game screen within the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+impl Sgb {
+	/// Composes the border and the colourised game screen as packed RGB
+	pub fn render(&self, shades: &[u8]) -> Vec<u8> {
+		let shades = if self.mask == Mask::Freeze {
+			&self.frozen[..]
+		} else {
+			shades
+		};
+		let backdrop = self.palettes[0][0];
+
+		let mut out = Vec::with_capacity(SGB_W * SGB_H * 3);
+		for y in 0..SGB_H {
+			for x in 0..SGB_W {
+				// The border sits on top of the game screen
+				let color = self
+					.border_pixel(x, y)
+					.or_else(|| self.screen_pixel(x, y, shades))
+					.unwrap_or(backdrop);
+
+				out.extend_from_slice(&rgb555(color));
+			}
+		}
+
+		out
+	}
+
+	/// Color of the border at a position, `None` if transparent
+	fn border_pixel(&self, x: usize, y: usize) -> Option<u16> {
+		let entry = self.border_map[(y / 8) * 32 + x / 8];
+		let tile = (entry & 0xFF) as usize;
+		// Only palettes 4-7 are available to the border
+		let palette = ((entry >> 10) & 0x3) as usize;
+
+		let px = if entry & 0x4000 > 0 { 7 - x % 8 } else { x % 8 };
+		let py = if entry & 0x8000 > 0 { 7 - y % 8 } else { y % 8 };
+
+		// Two 2bpp planes, one after the other
+		let data = &self.border_tiles[tile * 32..][..32];
+		let bit = 7 - px;
+		let color_no = (data[py * 2] >> bit & 0x1)
+			| (data[py * 2 + 1] >> bit & 0x1) << 1
+			| (data[16 + py * 2] >> bit & 0x1) << 2
+			| (data[16 + py * 2 + 1] >> bit & 0x1) << 3;
+
+		if color_no == 0 {
+			None
+		} else {
+			Some(self.border_palettes[palette][color_no as usize])
+		}
+	}
+
+	/// Color of the game screen at a position, `None` if outside of it
+	fn screen_pixel(&self, x: usize, y: usize, shades: &[u8]) -> Option<u16> {
+		let x = x.checked_sub(SCREEN_X).filter(|x| *x < 160)?;
+		let y = y.checked_sub(SCREEN_Y).filter(|y| *y < 144)?;
+
+		let color = match self.mask {
+			Mask::Black => 0,
+			Mask::Color0 => self.palettes[0][0],
+			Mask::None | Mask::Freeze => {
+				let palette = self.attr_map[(y / 8) * CELLS_W + x / 8] as usize;
+				self.palettes[palette][shades[y * 160 + x] as usize]
+			}
+		};
+
+		Some(color)
+	}
+}
@@ -0,0 +1,127 @@
+//! Timer and divider
+//!
+//! DIV is the upper byte of a 16 bit counter that runs at the cpu clock.
+//! TIMA counts the falling edges of one of the counter's bits, selected by
+//! TAC, and requests an interrupt when it overflows, starting over from TMA.
+//! Being driven by the cpu clock, all of it runs twice as fast in CGB double
+//! speed mode.
+
+/// Bit of the counter TIMA follows for each TAC clock select, for 4096,
+/// 262144, 65536 and 16384 Hz
+const CLOCK_BITS: [u16; 4] = [9, 3, 5, 7];
+
+/// Timer
+#[derive(Default)]
+pub struct Timer {
+	/// Internal counter, DIV [FF04] is its upper byte
+	counter: u16,
+	/// Timer counter [FF05]
+	tima: u8,
+	/// Timer modulo [FF06]
+	tma: u8,
+	/// Timer control [FF07], bit 2 enables the timer and bits 0-1 select the
+	/// clock
+	tac: u8,
+	/// Timer interrupt request
+	pub irq: bool,
+}
+
+impl Timer {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// The line TIMA counts the falling edges of
+	fn signal(&self) -> bool {
+		let bit = CLOCK_BITS[(self.tac & 0x3) as usize];
+		self.tac & 0x04 > 0 && self.counter >> bit & 1 > 0
+	}
+
+	/// Counts up TIMA if the line went low
+	fn edge(&mut self, before: bool) {
+		if !before || self.signal() {
+			return;
+		}
+
+		match self.tima.checked_add(1) {
+			Some(tima) => self.tima = tima,
+			None => {
+				self.tima = self.tma;
+				self.irq = true;
+			}
+		}
+	}
+
+	pub fn read(&self, addr: usize) -> u8 {
+		match addr {
+			0xFF04 => (self.counter >> 8) as u8,
+			0xFF05 => self.tima,
+			0xFF06 => self.tma,
+			0xFF07 => 0xF8 | self.tac,
+			_ => unreachable!(),
+		}
+	}
+
+	pub fn write(&mut self, addr: usize, val: u8) {
+		let before = self.signal();
+
+		match addr {
+			// Any write clears the whole counter
+			0xFF04 => self.counter = 0,
+			0xFF05 => self.tima = val,
+			0xFF06 => self.tma = val,
+			0xFF07 => self.tac = val & 0x07,
+			_ => unreachable!(),
+		}
+
+		// Clearing the counter or switching the clock can pull the line low
+		self.edge(before);
+	}
+
+	/// Runs for the given number of cpu T-cycles
+	pub fn update(&mut self, cycles: u8) {
+		if self.tac & 0x04 == 0 {
+			self.counter = self.counter.wrapping_add(cycles as u16);
+			return;
+		}
+
+		// The fastest clock has a period of 16 cycles, so no edge is missed
+		// going an M-cycle at a time
+		for _ in 0..cycles / 4 {
+			let before = self.signal();
+			self.counter = self.counter.wrapping_add(4);
+			self.edge(before);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_timer() {
+		let mut timer = Timer::new();
+		timer.update(252);
+		assert_eq!(timer.read(0xFF04), 0);
+		timer.update(4);
+		assert_eq!(timer.read(0xFF04), 1);
+
+		// 262144 Hz, overflowing after 2 increments
+		timer.write(0xFF05, 0xFE);
+		timer.write(0xFF06, 0x80);
+		timer.write(0xFF07, 0x05);
+		timer.update(16);
+		assert_eq!(timer.read(0xFF05), 0xFF);
+		assert!(!timer.irq);
+		timer.update(16);
+		assert_eq!(timer.read(0xFF05), 0x80);
+		assert!(timer.irq);
+
+		// Clearing DIV while the selected bit is set counts once more
+		timer.update(8);
+		timer.write(0xFF04, 0x00);
+		assert_eq!(timer.read(0xFF04), 0);
+		assert_eq!(timer.read(0xFF05), 0x81);
+	}
+}
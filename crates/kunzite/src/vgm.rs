@@ -0,0 +1,140 @@
+//! Logging APU register writes to VGM files
+//!
+//! VGM stores the writes to the sound chip with the time between them in
+//! 44.1 kHz samples, which is enough for players to recreate the sound.
+//! Version 1.61 added the Game Boy DMG, whose writes are stored as offsets
+//! from NR10.
+
+use crate::audio::CLOCK_RATE;
+use color_eyre::Result;
+use std::{fs, path::Path};
+
+/// Rate VGM counts time in
+const SAMPLE_RATE: u64 = 44_100;
+/// Size of the header, the data follows right after
+const HEADER_SIZE: usize = 0x100;
+
+/// Collects APU writes in memory until the log is saved
+pub struct VgmLog {
+	/// Command stream
+	data: Vec<u8>,
+	/// Clocks since the log was started
+	clocks: u64,
+	/// Samples waited so far
+	samples: u64,
+}
+
+impl VgmLog {
+	pub fn new() -> Self {
+		Self {
+			data: Vec::new(),
+			clocks: 0,
+			samples: 0,
+		}
+	}
+
+	/// Lets time pass
+	pub fn advance(&mut self, clocks: u32) {
+		self.clocks += clocks as u64;
+	}
+
+	/// Logs a write to 0xFF10-0xFF3F
+	pub fn write(&mut self, addr: usize, val: u8) {
+		self.wait();
+		self.data
+			.extend_from_slice(&[0xB3, (addr - 0xFF10) as u8, val]);
+	}
+
+	/// Catches up on the time passed since the last command
+	///
+	/// The sample is derived from the total clocks each time, so rounding
+	/// never adds up.
+	fn wait(&mut self) {
+		let target = self.clocks * SAMPLE_RATE / CLOCK_RATE as u64;
+		let mut wait = target - self.samples;
+		self.samples = target;
+
+		while wait > 0 {
+			match wait {
+				1..=16 => {
+					self.data.push(0x70 + (wait - 1) as u8);
+					wait = 0;
+				}
+				735 => {
+					// One NTSC frame
+					self.data.push(0x62);
+					wait = 0;
+				}
+				882 => {
+					// One PAL frame
+					self.data.push(0x63);
+					wait = 0;
+				}
+				_ => {
+					let n = wait.min(0xFFFF);
+					self.data.push(0x61);
+					self.data.extend_from_slice(&(n as u16).to_le_bytes());
+					wait -= n;
+				}
+			}
+		}
+	}
+
+	/// Length of the log so far in seconds
+	pub fn seconds(&self) -> f64 {
+		self.clocks as f64 / CLOCK_RATE as f64
+	}
+
+	/// Builds the file
+	pub fn finish(mut self) -> Vec<u8> {
+		self.wait();
+		self.data.push(0x66); // end of sound data
+
+		let mut file = vec![0; HEADER_SIZE];
+		let mut put = |offset: usize, val: u32| {
+			file[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+		};
+
+		let len = (HEADER_SIZE + self.data.len()) as u32;
+		put(0x00, u32::from_le_bytes(*b"Vgm "));
+		put(0x04, len - 0x04);
+		put(0x08, 0x161);
+		put(0x18, self.samples as u32);
+		// Relative to the field itself
+		put(0x34, HEADER_SIZE as u32 - 0x34);
+		put(0x80, CLOCK_RATE);
+
+		file.extend_from_slice(&self.data);
+		file
+	}
+
+	pub fn save<P: AsRef<Path>>(self, path: P) -> Result<()> {
+		fs::write(path, self.finish())?;
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_log() {
+		let mut log = VgmLog::new();
+		log.write(0xFF26, 0x80);
+		// A second later
+		log.advance(CLOCK_RATE);
+		log.write(0xFF30, 0x12);
+		let file = log.finish();
+
+		assert_eq!(&file[0..4], b"Vgm ");
+		assert_eq!(&file[0x18..0x1C], &44_100u32.to_le_bytes());
+		assert_eq!(&file[HEADER_SIZE..], &[
+			0xB3, 0x16, 0x80, // NR52
+			0x61, 0x44, 0xAC, // 44100 samples
+			0xB3, 0x20, 0x12, // wave RAM
+			0x66,
+		]);
+	}
+}
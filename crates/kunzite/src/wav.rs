@@ -0,0 +1,209 @@
+//! Recording the APU output to WAV files
+//!
+//! The mix goes to the given path, each channel's stem optionally next to
+//! it as `<name>-ch1.wav` to `<name>-ch4.wav`. Samples are written as 16 bit
+//! stereo PCM and nothing but the samples ends up in the files, so the same
+//! run always produces the same bytes.
+
+use crate::audio::CHANNELS;
+use color_eyre::{eyre::eyre, Result};
+use std::{
+	fs::File,
+	io::{BufWriter, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+};
+
+/// Size of the RIFF and format headers up to the data
+const HEADER_SIZE: u32 = 44;
+
+/// A single 16 bit stereo WAV file
+struct WavFile {
+	file: BufWriter<File>,
+	/// Bytes of sample data written so far
+	data_size: u32,
+}
+
+impl WavFile {
+	fn create(path: &Path, sample_rate: u32) -> Result<Self> {
+		let mut file = BufWriter::new(File::create(path)?);
+
+		let channels = 2u16;
+		let block_align = channels * 2;
+		file.write_all(b"RIFF")?;
+		// Sizes are patched in once done
+		file.write_all(&0u32.to_le_bytes())?;
+		file.write_all(b"WAVEfmt ")?;
+		file.write_all(&16u32.to_le_bytes())?;
+		// PCM
+		file.write_all(&1u16.to_le_bytes())?;
+		file.write_all(&channels.to_le_bytes())?;
+		file.write_all(&sample_rate.to_le_bytes())?;
+		file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+		file.write_all(&block_align.to_le_bytes())?;
+		file.write_all(&16u16.to_le_bytes())?;
+		file.write_all(b"data")?;
+		file.write_all(&0u32.to_le_bytes())?;
+
+		Ok(Self { file, data_size: 0 })
+	}
+
+	/// Writes interleaved stereo samples
+	fn write(&mut self, samples: &[f32]) -> Result<()> {
+		for sample in samples {
+			let sample = (sample.max(-1.0).min(1.0) * i16::MAX as f32).round() as i16;
+			self.file.write_all(&sample.to_le_bytes())?;
+		}
+
+		self.data_size = samples
+			.len()
+			.checked_mul(2)
+			.and_then(|bytes| self.data_size.checked_add(bytes as u32))
+			.ok_or_else(|| eyre!("WAV file is too large"))?;
+
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<()> {
+		self.file.seek(SeekFrom::Start(4))?;
+		self.file
+			.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+		self.file.seek(SeekFrom::Start(40))?;
+		self.file.write_all(&self.data_size.to_le_bytes())?;
+		self.file.flush()?;
+
+		Ok(())
+	}
+}
+
+/// Writes the mix and optionally the stems of each channel
+pub struct WavRecorder {
+	mix: WavFile,
+	stems: Option<Vec<WavFile>>,
+	sample_rate: u32,
+	/// Stereo samples written so far
+	samples: u64,
+}
+
+impl WavRecorder {
+	pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, stems: bool) -> Result<Self> {
+		let path = path.as_ref();
+
+		let stems = if stems {
+			let files = (1..=CHANNELS)
+				.map(|channel| WavFile::create(&stem_path(path, channel), sample_rate))
+				.collect::<Result<_>>()?;
+			Some(files)
+		} else {
+			None
+		};
+
+		Ok(Self {
+			mix: WavFile::create(path, sample_rate)?,
+			stems,
+			sample_rate,
+			samples: 0,
+		})
+	}
+
+	/// Writes the samples of the mix and, if recorded, the stems
+	pub fn write(&mut self, samples: &[f32], stems: Option<&[Vec<f32>]>) -> Result<()> {
+		self.mix.write(samples)?;
+		self.samples += samples.len() as u64 / 2;
+
+		if let (Some(files), Some(stems)) = (&mut self.stems, stems) {
+			for (file, stem) in files.iter_mut().zip(stems) {
+				file.write(stem)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Length of the recording so far in seconds
+	pub fn seconds(&self) -> f64 {
+		self.samples as f64 / self.sample_rate as f64
+	}
+
+	pub fn finish(self) -> Result<()> {
+		self.mix.finish()?;
+		for file in self.stems.into_iter().flatten() {
+			file.finish()?;
+		}
+
+		Ok(())
+	}
+}
+
+/// `out.wav` becomes `out-ch1.wav` for channel 1
+fn stem_path(path: &Path, channel: usize) -> PathBuf {
+	let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("audio");
+	path.with_file_name(format!("{}-ch{}.wav", stem, channel))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		audio::{Audio, DEFAULT_SAMPLE_RATE},
+		gb::FRAME_CYCLES,
+	};
+	use std::{env, fs};
+
+	/// Records `frames` frames of a square wave with stems, returns the mix
+	fn record(path: &Path, frames: u32) -> Vec<u8> {
+		let mut audio = Audio::new();
+		audio.set_stems(true);
+		audio.write(0xFF26, 0x80);
+		audio.write(0xFF25, 0x11);
+		audio.write(0xFF24, 0x77);
+		audio.write(0xFF12, 0xF0);
+		audio.write(0xFF14, 0x87);
+
+		let mut wav = WavRecorder::create(path, DEFAULT_SAMPLE_RATE, true).unwrap();
+		for _ in 0..frames {
+			for _ in 0..FRAME_CYCLES / 4 {
+				audio.update(4);
+			}
+			let stems = audio.take_stems();
+			wav.write(&audio.take_samples(), stems.as_deref()).unwrap();
+		}
+		wav.finish().unwrap();
+
+		let bytes = fs::read(path).unwrap();
+		fs::remove_file(path).unwrap();
+		for channel in 1..=CHANNELS {
+			fs::remove_file(stem_path(path, channel)).unwrap();
+		}
+		bytes
+	}
+
+	fn read_u32(bytes: &[u8], at: usize) -> u32 {
+		u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+	}
+
+	#[test]
+	fn test_deterministic() {
+		let path = env::temp_dir().join("kunzite-wav-test.wav");
+		let first = record(&path, 60);
+		assert_eq!(record(&path, 60), first);
+
+		// The sizes patched in at the end
+		assert_eq!(read_u32(&first, 4) as usize, first.len() - 8);
+		assert_eq!(
+			read_u32(&first, 40) as usize,
+			first.len() - HEADER_SIZE as usize
+		);
+		// About a second of 16 bit stereo, and not silent
+		let data = &first[HEADER_SIZE as usize..];
+		assert!(data.len() / 4 > DEFAULT_SAMPLE_RATE as usize * 99 / 100);
+		assert!(data.iter().any(|&b| b != 0));
+	}
+
+	#[test]
+	fn test_stem_path() {
+		assert_eq!(
+			stem_path(Path::new("rips/song.wav"), 3),
+			PathBuf::from("rips/song-ch3.wav")
+		);
+	}
+}